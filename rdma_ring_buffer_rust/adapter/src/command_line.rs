@@ -1,18 +1,152 @@
 use std::num::NonZeroI32;
 
-use clap::{arg, command, Parser};
+use clap::{arg, command, Args, Parser, ValueEnum};
+use shared::{
+    ipc::{
+        config::{IpcEndpoint, PeerRule},
+        element_type::ElementType,
+    },
+    rdma_controller::config::{mtu_from_bytes, QpConfig},
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct GlobalArgs {
+    /// Name of the IB device to open, e.g. mlx5_0
     #[arg(short, long, default_value = "mlx5_0")]
     pub dev: String,
+    /// Port of the IB device to use
+    #[arg(long, default_value_t = 1)]
+    pub ib_port: u8,
     #[arg(short, long, default_value = "1")]
     pub gid_index: Option<NonZeroI32>,
     #[arg(short, long)]
     pub server_addr: Option<String>,
     #[arg(short, long)]
     pub port: Option<u16>,
+    /// Number of elements per RDMA message
     #[arg(short, long, default_value_t = 64usize)]
     pub message_size: usize,
+    /// Ring element type: bytes, a primitive such as u64, or record:<size>:<align>:<name>
+    #[arg(short, long, default_value = "u64")]
+    pub element: ElementType,
+    #[arg(long)]
+    pub ipc_path: Option<IpcEndpoint>,
+    /// Peers allowed to attach, as uid:<n>, gid:<n> or pid:<n>. Defaults to our own uid.
+    #[arg(long = "ipc-allow")]
+    pub ipc_allow: Vec<PeerRule>,
+    /// How ring data reaches the remote side, both adapters must agree
+    #[arg(long, value_enum, default_value_t = Mode::SendRecv)]
+    pub mode: Mode,
+    /// Empty CQ polls before sleeping on the completion channel, busy polls if unset
+    #[arg(long)]
+    pub idle_polls: Option<u32>,
+    /// Longest sleep on the completion channel in microseconds, rounded up to whole milliseconds, bounds how late new ring data is noticed
+    #[arg(long, default_value_t = 1000)]
+    pub max_wait_us: u64,
+    /// Completions taken from the CQ per poll [default: 16]
+    #[arg(long)]
+    pub poll_batch: Option<usize>,
+    /// Connect through rdma_cm on --port, GID and path MTU are resolved from the address
+    #[arg(long)]
+    pub rdma_cm: bool,
+    #[command(flatten)]
+    pub qp: QpArgs,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // Two-sided, the remote adapter posts a recv for every message
+    SendRecv,
+    // One-sided, ring data and tail are written straight into the remote ring
+    Write,
+    // One-sided, each adapter reads the remote outbound ring into its inbound ring
+    Read,
+}
+
+// Unset values are derived from the port and device attributes
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Queue pair")]
+pub struct QpArgs {
+    /// Path MTU in bytes [default: active MTU of the port]
+    #[arg(long, value_parser = parse_mtu)]
+    pub mtu: Option<u32>,
+    /// Minimal RNR NAK timer code [default: 12]
+    #[arg(long)]
+    pub min_rnr_timer: Option<u8>,
+    /// Local ack timeout code [default: 14]
+    #[arg(long)]
+    pub timeout: Option<u8>,
+    /// Retries on timeout [default: 7]
+    #[arg(long)]
+    pub retry_cnt: Option<u8>,
+    /// Retries on RNR NAK, 7 retries forever [default: 7]
+    #[arg(long)]
+    pub rnr_retry: Option<u8>,
+    /// Outstanding RDMA READs and atomics we initiate [default: device maximum]
+    #[arg(long)]
+    pub max_rd_atomic: Option<u8>,
+    /// Outstanding RDMA READs and atomics we serve [default: device maximum]
+    #[arg(long)]
+    pub max_dest_rd_atomic: Option<u8>,
+    /// Source GID index of the GRH [default: --gid-index]
+    #[arg(long)]
+    pub sgid_index: Option<u8>,
+    /// Hop limit of the GRH [default: 1]
+    #[arg(long)]
+    pub hop_limit: Option<u8>,
+    /// Traffic class of the GRH [default: 0]
+    #[arg(long)]
+    pub traffic_class: Option<u8>,
+    /// Service level [default: 0]
+    #[arg(long)]
+    pub service_level: Option<u8>,
+    /// Send queue depth [default: min(8192, device maximum)]
+    #[arg(long)]
+    pub max_send_wr: Option<u32>,
+    /// Receive queue depth [default: min(8192, device maximum)]
+    #[arg(long)]
+    pub max_recv_wr: Option<u32>,
+    /// Scatter/gather entries per send WR [default: min(3, device maximum)]
+    #[arg(long)]
+    pub max_send_sge: Option<u32>,
+    /// Scatter/gather entries per receive WR [default: min(3, device maximum)]
+    #[arg(long)]
+    pub max_recv_sge: Option<u32>,
+    /// Bytes a send may carry inline, smaller sends skip the DMA read [default: 64, or none if the device refuses]
+    #[arg(long)]
+    pub max_inline_data: Option<u32>,
+    /// Send WRs per signaled completion, at most half the send queue [default: 64]
+    #[arg(long)]
+    pub signal_interval: Option<u32>,
+}
+
+impl From<QpArgs> for QpConfig {
+    fn from(args: QpArgs) -> Self {
+        QpConfig {
+            path_mtu: args.mtu,
+            min_rnr_timer: args.min_rnr_timer,
+            timeout: args.timeout,
+            retry_cnt: args.retry_cnt,
+            rnr_retry: args.rnr_retry,
+            max_rd_atomic: args.max_rd_atomic,
+            max_dest_rd_atomic: args.max_dest_rd_atomic,
+            sgid_index: args.sgid_index,
+            hop_limit: args.hop_limit,
+            traffic_class: args.traffic_class,
+            service_level: args.service_level,
+            max_send_wr: args.max_send_wr,
+            max_recv_wr: args.max_recv_wr,
+            max_send_sge: args.max_send_sge,
+            max_recv_sge: args.max_recv_sge,
+            max_inline_data: args.max_inline_data,
+            signal_interval: args.signal_interval,
+        }
+    }
+}
+
+fn parse_mtu(s: &str) -> Result<u32, String> {
+    let bytes = s.parse().map_err(|_| format!("invalid path MTU {s:?}"))?;
+    mtu_from_bytes(bytes)?;
+    Ok(bytes)
 }
@@ -1,25 +1,47 @@
 use core::panic;
 use std::{
     io::{Read, Write},
-    mem::{offset_of, size_of, transmute, MaybeUninit},
+    mem::{align_of, offset_of, size_of, transmute, MaybeUninit},
     net::IpAddr,
     ops::{Deref, DerefMut},
     process::exit,
+    ptr::slice_from_raw_parts_mut,
     str::FromStr,
+    sync::atomic::AtomicUsize,
     thread,
+    time::Duration,
 };
 
 use clap::Parser;
 
 use shared::{
-    ipc::{self, ring_buffer_metadata::RingBufferMetaData},
-    rdma_controller,
-    ring_buffer::RingBufferConst,
+    ipc::{
+        self,
+        config::{IpcConfig, IpcEndpoint},
+        element_type::ElementType,
+        ring_buffer_metadata::{RingBufferMetaData, RingDescriptor},
+    },
+    rdma_controller::{
+        self,
+        config::{Bootstrap, CompletionMode},
+        memory_region::MemoryRegion,
+        send::{RecvBatch, SendBatch, SendFlagBuilder},
+        work_completion::WorkCompletion,
+        IbResource,
+    },
+    ref_ring_buffer::{
+        reader_chunk::ReadChunk, receiver::Receiver, sender::Sender, writer_chunk::WriteChunk,
+    },
+    ring_buffer::RingBufferHeader,
 };
 use shared_memory::ShmemConf;
 use uninit::out_ref::Out;
+use zerocopy::{AsBytes, FromBytes};
 
-use crate::{atomic_extension::AtomicExtension, command_line::GlobalArgs};
+use crate::{
+    atomic_extension::AtomicExtension,
+    command_line::{GlobalArgs, Mode},
+};
 
 mod atomic_extension;
 mod command_line;
@@ -42,20 +64,95 @@ pub fn main() {
 
     let mut ib_resource = rdma_controller::IbResource::new();
 
+    let completion = match args.idle_polls {
+        Some(idle_polls) => CompletionMode::Hybrid {
+            idle_polls,
+            max_wait: Duration::from_micros(args.max_wait_us),
+        },
+        None => CompletionMode::Polling,
+    };
+
     let config = rdma_controller::config::Config {
         dev_name: args.dev,
+        port_num: args.ib_port,
         connection_type: connection_type.clone(),
         gid_index: args.gid_index,
+        qp: args.qp.into(),
+        completion,
+        poll_batch: args.poll_batch,
+        recv_queue: Default::default(),
+        transport: Default::default(),
+        bootstrap: if args.rdma_cm {
+            Bootstrap::RdmaCm
+        } else {
+            Bootstrap::Tcp
+        },
     };
 
-    ib_resource.setup_ib(config).unwrap();
+    // Connecting waits until the ring memory is registered and advertised
+    if let Err(err) = ib_resource.open(config) {
+        eprintln!("Failed to set up IB: {}", err);
+        exit(1);
+    }
 
-    println!("IB setup done");
+    let ipc_config = IpcConfig {
+        endpoint: args.ipc_path.unwrap_or(IpcEndpoint::Default),
+        allowed_peers: args.ipc_allow,
+        ..Default::default()
+    };
+
+    let element_type = args.element;
+    let message_size = args.message_size;
+    let mode = args.mode;
+
+    // The adapter only moves slots around, so any type with the same layout will do
+    match (element_type.size, element_type.align) {
+        (1, 1) => serve::<u8>(ib_resource, ipc_config, element_type, message_size, mode),
+        (2, 2) => serve::<u16>(ib_resource, ipc_config, element_type, message_size, mode),
+        (4, 4) => serve::<u32>(ib_resource, ipc_config, element_type, message_size, mode),
+        (8, 8) => serve::<u64>(ib_resource, ipc_config, element_type, message_size, mode),
+        (16, 8) => serve::<[u64; 2]>(ib_resource, ipc_config, element_type, message_size, mode),
+        (32, 8) => serve::<[u64; 4]>(ib_resource, ipc_config, element_type, message_size, mode),
+        (64, 8) => serve::<[u64; 8]>(ib_resource, ipc_config, element_type, message_size, mode),
+        (128, 8) => serve::<[u64; 16]>(ib_resource, ipc_config, element_type, message_size, mode),
+        (256, 8) => serve::<[u64; 32]>(ib_resource, ipc_config, element_type, message_size, mode),
+        // ElementType::from_str only accepts the layouts above
+        (size, align) => unreachable!("unsupported element layout {}/{}", size, align),
+    }
+}
+
+// Words used by the one-sided modes, placed after the two rings
+#[repr(C, align(4096))]
+struct ReplicationState {
+    // Head of the remote inbound ring, written by the remote adapter
+    peer_head: AtomicUsize,
+    // Sources of the tail and head words we write to the remote side, they
+    // must stay untouched until the write completes. The read mode reads the
+    // remote tail into tail_staging.
+    tail_staging: [usize; 1],
+    head_staging: [usize; 1],
+}
+
+fn serve<T: FromBytes + AsBytes + Copy + Send>(
+    mut ib_resource: IbResource,
+    ipc_config: IpcConfig,
+    element_type: ElementType,
+    message_size: usize,
+    mode: Mode,
+) {
+    // Same footprint as the old 1 << 20 u64 ring, whatever the element size
+    const RING_BYTES: usize = 8 << 20;
+
+    assert_eq!(size_of::<T>() as u64, element_type.size);
+    assert_eq!(align_of::<T>() as u32, element_type.align);
 
-    const RINGBUFFER_LEN: usize = 1 << 20;
+    let ring_len = RING_BYTES / size_of::<T>();
+    let ring_size = size_of::<RingBufferHeader>() + RING_BYTES;
+    let state_offset = 2 * ring_size;
 
+    // The outbound ring (host -> remote) is followed by the inbound ring (remote -> host)
     let mut shmem = ShmemConf::new()
-        .size(size_of::<RingBufferConst<u64, RINGBUFFER_LEN>>())
+        .size(state_offset + size_of::<ReplicationState>())
         .create()
         .unwrap();
 
@@ -67,30 +164,114 @@ pub fn main() {
             .unwrap()
     };
 
-    let ring_buffer = unsafe {
-        let uninit = shmem
-            .as_ptr()
-            .cast::<MaybeUninit<RingBufferConst<u64, RINGBUFFER_LEN>>>()
+    let describe = |index: usize| RingDescriptor {
+        head_offset: index * ring_size + offset_of!(RingBufferHeader, head),
+        tail_offset: index * ring_size + offset_of!(RingBufferHeader, tail),
+        buffer_offset: index * ring_size + size_of::<RingBufferHeader>(),
+        ring_buffer_len: ring_len,
+    };
+
+    let ring_at = |descriptor: &RingDescriptor| unsafe {
+        let base = shmem.as_ptr();
+
+        let header = base
+            .add(descriptor.head_offset - offset_of!(RingBufferHeader, head))
+            .cast::<MaybeUninit<RingBufferHeader>>()
             .as_mut()
             .unwrap();
 
-        uninit.write(RingBufferConst::new());
+        let header = header.write(RingBufferHeader::new());
 
-        uninit.assume_init_mut()
+        header.to_ref::<T>(slice_from_raw_parts_mut(
+            base.add(descriptor.buffer_offset).cast(),
+            descriptor.ring_buffer_len,
+        ))
     };
 
-    println!("RingBuffer: {:p}", ring_buffer);
-    println!("RingBuffer: {:p}", &ring_buffer.head);
-    println!("RingBuffer: {:p}", &ring_buffer.tail);
-    println!("RingBuffer: {:p}", &ring_buffer.buffer);
+    let outbound = describe(0);
+    let inbound = describe(1);
 
-    let mut ring_buffer = ring_buffer.to_ref();
+    // Set up before connecting, the remote adapter may start writing right after
+    let state = unsafe {
+        shmem
+            .as_ptr()
+            .add(state_offset)
+            .cast::<MaybeUninit<ReplicationState>>()
+            .as_mut()
+            .unwrap()
+            .write(ReplicationState {
+                peer_head: AtomicUsize::new(0),
+                tail_staging: [0],
+                head_staging: [0],
+            })
+    };
+
+    let mut outbound_ring = ring_at(&outbound);
+    let mut inbound_ring = ring_at(&inbound);
+
+    let inbound_header = unsafe {
+        &*shmem
+            .as_ptr()
+            .add(inbound.head_offset - offset_of!(RingBufferHeader, head))
+            .cast::<RingBufferHeader>()
+    };
 
-    let (sender, receiver) = ring_buffer.split();
+    if mode == Mode::Write {
+        let header_offset = inbound.head_offset - offset_of!(RingBufferHeader, head);
+        let credit_offset = state_offset + offset_of!(ReplicationState, peer_head);
+
+        let regions = [
+            (
+                "ring-data",
+                inbound.buffer_offset..inbound.buffer_offset + RING_BYTES,
+            ),
+            (
+                "ring-indices",
+                header_offset..header_offset + size_of::<RingBufferHeader>(),
+            ),
+            (
+                "ring-credit",
+                credit_offset..credit_offset + size_of::<AtomicUsize>(),
+            ),
+        ];
+
+        for (name, range) in regions {
+            ib_resource.advertise(mr.remote_region(name, range).unwrap());
+        }
+    }
+
+    if mode == Mode::Read {
+        let header_offset = outbound.head_offset - offset_of!(RingBufferHeader, head);
+
+        let regions = [
+            (
+                "outbound-data",
+                outbound.buffer_offset..outbound.buffer_offset + RING_BYTES,
+            ),
+            (
+                "outbound-indices",
+                header_offset..header_offset + size_of::<RingBufferHeader>(),
+            ),
+        ];
+
+        for (name, range) in regions {
+            ib_resource.advertise(mr.remote_region(name, range).unwrap());
+        }
+    }
+
+    if let Err(err) = ib_resource.connect() {
+        eprintln!("Failed to connect: {}", err);
+        exit(1);
+    }
+
+    println!("IB setup done");
+
+    let (_, outbound_receiver) = outbound_ring.split();
+    let (inbound_sender, _) = inbound_ring.split();
 
     println!("Creating IPC");
 
-    let mut ipc = ipc::Ipc::create("sync");
+    let mut ipc = ipc::Ipc::create(&ipc_config).expect("Failed to create IPC");
 
     println!("IPC created");
 
@@ -100,17 +281,18 @@ pub fn main() {
     name_buffer[..name.len()].copy_from_slice(name);
 
     let init_metadata = RingBufferMetaData {
-        head_offset: offset_of!(RingBufferConst<u64, RINGBUFFER_LEN>, head),
-        tail_offset: offset_of!(RingBufferConst<u64, RINGBUFFER_LEN>, tail),
-        buffer_offset: offset_of!(RingBufferConst<u64, RINGBUFFER_LEN>, buffer),
-        ring_buffer_len: RINGBUFFER_LEN,
+        outbound,
+        inbound,
+        element_type,
         shared_memory_name_len: name.len(),
         shared_memory_name: name_buffer,
     };
 
     println!("Metadata: {:?}", init_metadata);
 
-    init_metadata.write_to(&mut ipc);
+    init_metadata
+        .write_to(&mut ipc)
+        .expect("Failed to send metadata");
 
     let _ipc_thread = thread::spawn(move || {
         let mut buf = vec![0];
@@ -118,94 +300,516 @@ pub fn main() {
         exit(0);
     });
 
-    let mut expected_val: u64 = 0;
+    match mode {
+        Mode::SendRecv => send_recv(
+            &mut ib_resource,
+            &mut mr,
+            &outbound_receiver,
+            &inbound_sender,
+            message_size,
+        ),
+        Mode::Write => replicate_write(
+            &mut ib_resource,
+            &mut mr,
+            &outbound_receiver,
+            inbound_header,
+            state,
+            ring_len,
+            message_size,
+        ),
+        Mode::Read => replicate_read(
+            &mut ib_resource,
+            &mut mr,
+            &inbound_sender,
+            state,
+            ring_len,
+            message_size,
+        ),
+    }
+}
+
+fn send_recv<T: FromBytes + AsBytes + Copy + Send>(
+    ib_resource: &mut IbResource,
+    mr: &mut MemoryRegion,
+    outbound_receiver: &Receiver<T>,
+    inbound_sender: &Sender<T>,
+    message_size: usize,
+) {
+    const SEND_WR_ID: u64 = 2;
+    const RECV_WR_ID: u64 = 3;
+    const SEND_LAST_WR_ID: u64 = 10;
+    // Messages chained into one post, amortizes the doorbell over small messages
+    const BATCH_MESSAGES: usize = 32;
+
+    // At most one batch of sends and one of recvs are in flight, each pinning
+    // its ring chunk. A message that wraps around the end of the ring goes out
+    // with two SGEs. Only the last send of a batch is sure to be signaled, its
+    // completion means the whole batch was sent.
+    let mut send_batch = SendBatch::new();
+    let mut recv_batch = RecvBatch::new();
+    let mut pending_send: Option<ReadChunk<T>> = None;
+    let mut pending_recv: Option<(WriteChunk<T>, usize)> = None;
+    let mut received = 0;
+    // Inbound ring position, where the peer resumes after a reconnect
+    let mut inbound_position = 0;
+
+    loop {
+        if pending_send.is_none() {
+            let mut reader = outbound_receiver.read_wrapping(BATCH_MESSAGES * message_size);
+
+            let messages = (reader.len() / message_size).min(ib_resource.send_queue_room());
+
+            if messages > 0 {
+                reader.truncate(messages * message_size);
+
+                send_batch.clear();
+
+                for index in 0..messages {
+                    let (first, wrapped) = reader.slices_at(index * message_size, message_size);
+
+                    if index + 1 == messages {
+                        send_batch.send(
+                            SEND_LAST_WR_ID,
+                            [(&*mr, first), (&*mr, wrapped)],
+                            SendFlagBuilder::new().signaled().build(),
+                        );
+                    } else {
+                        send_batch.send(
+                            SEND_WR_ID,
+                            [(&*mr, first), (&*mr, wrapped)],
+                            SendFlagBuilder::new().build(),
+                        );
+                    }
+                }
 
-    match connection_type {
-        rdma_controller::config::ConnectionType::Server { message_size, .. } => loop {
-            unsafe {
-                if let Some(mut writer) = sender.try_reserve(message_size) {
-                    assert_eq!(writer.len(), message_size);
+                unsafe {
+                    ib_resource
+                        .post_send_batch(&mut send_batch)
+                        .expect("Failed to post sends");
+                }
+
+                pending_send = Some(reader);
+            }
+        }
 
+        if pending_recv.is_none() {
+            let mut writer = inbound_sender.reserve_wrapping(BATCH_MESSAGES * message_size);
+
+            let messages = writer.len() / message_size;
+
+            if messages > 0 {
+                writer.truncate(messages * message_size);
+
+                recv_batch.clear();
+
+                for index in 0..messages {
+                    let (first, wrapped) = writer.slices_at(index * message_size, message_size);
+
+                    recv_batch.recv(
+                        RECV_WR_ID,
+                        [
+                            (&*mr, Out::<'_, [T]>::from(first)),
+                            (&*mr, Out::<'_, [T]>::from(wrapped)),
+                        ],
+                    );
+                }
+
+                unsafe {
                     ib_resource
-                        .post_recv(2, &mut mr, Out::<'_, [u64]>::from(writer.deref_mut()))
-                        .expect("Failed to post recv");
-
-                    'outer: loop {
-                        for wc in ib_resource.poll_cq() {
-                            if wc.status != rdma_sys::ibv_wc_status::IBV_WC_SUCCESS {
-                                eprintln!("Buffer Address: {:?}", writer.as_ptr() as *const u64);
-                                panic!(
-                                    "wc status {}, last error {}",
-                                    wc.status,
-                                    std::io::Error::last_os_error()
-                                );
-                            }
-
-                            if wc.opcode == rdma_sys::ibv_wc_opcode::IBV_WC_RECV {
-                                break 'outer;
-                            }
+                        .post_recv_batch(&mut recv_batch)
+                        .expect("Failed to post recvs");
+                }
+
+                received = 0;
+                pending_recv = Some((writer, messages));
+            }
+        }
+
+        let failed = poll_completions(ib_resource, |wc| {
+            match wc.wr_id {
+                SEND_LAST_WR_ID => {
+                    if let Some(mut reader) = pending_send.take() {
+                        reader.commit();
+                    }
+                }
+                // Recvs complete in posting order, publish each message as it lands
+                RECV_WR_ID => {
+                    if let Some((writer, messages)) = pending_recv.as_mut() {
+                        received += 1;
+
+                        writer.commit_prefix(received * message_size);
+                        inbound_position += message_size;
+
+                        if received == *messages {
+                            pending_recv = None;
                         }
                     }
+                }
+                _ => {}
+            }
+        });
+
+        if failed {
+            // Recvs that did not complete are posted again
+            pending_recv = None;
 
-                    // for val in 0..writer.len() {
-                    //     if writer[val].assume_init() != expected_val {
-                    //         eprintln!(
-                    //             "Expected: {}, Got: {}",
-                    //             expected_val,
-                    //             writer[val].assume_init()
-                    //         );
-                    //         eprintln!(
-                    //             "Buffer: {:?}",
-                    //             transmute::<&mut [MaybeUninit<u64>], &mut [u64]>(
-                    //                 writer.deref_mut()
-                    //             )
-                    //         );
-                    //         panic!("");
-                    //     }
-                    //     expected_val = expected_val.wrapping_add(1);
-                    // }
-
-                    writer.commit();
+            let peer_position = reconnect(ib_resource, inbound_position);
+
+            // Sends that reached the peer are done even if their completion got lost
+            if let Some(mut reader) = pending_send.take() {
+                let delivered = peer_position.unwrap_or(reader.start);
+
+                reader.truncate(delivered.clamp(reader.start, reader.end) - reader.start);
+                reader.commit();
+            }
+        }
+    }
+}
+
+// Pushes the outbound ring into the remote inbound ring with RDMA WRITEs: the
+// data first, then the new tail. RC executes the writes in order, so the
+// remote host never sees a tail ahead of its data and no remote CPU is
+// involved. The remote adapter writes the head of its inbound ring back into
+// our ReplicationState the same way, which bounds how far we may run ahead.
+fn replicate_write<T: FromBytes + AsBytes + Copy + Send>(
+    ib_resource: &mut IbResource,
+    mr: &mut MemoryRegion,
+    outbound_receiver: &Receiver<T>,
+    inbound_header: &RingBufferHeader,
+    state: &mut ReplicationState,
+    ring_len: usize,
+    message_size: usize,
+) {
+    const DATA_WR_ID: u64 = 4;
+    const TAIL_WR_ID: u64 = 5;
+    const HEAD_WR_ID: u64 = 6;
+
+    let remote_region = |name: &str| match ib_resource.remote_region(name) {
+        Some(region) => region.clone(),
+        None => {
+            eprintln!(
+                "Remote side did not advertise {}, is it in write mode?",
+                name
+            );
+            exit(1);
+        }
+    };
+
+    let remote_data = remote_region("ring-data");
+    let remote_indices = remote_region("ring-indices");
+    let remote_credit = remote_region("ring-credit");
+
+    // Positions are mirrored, so both rings must have the same length
+    if remote_data.len != (ring_len * size_of::<T>()) as u64 {
+        eprintln!(
+            "Remote ring holds {} bytes, ours {}",
+            remote_data.len,
+            ring_len * size_of::<T>()
+        );
+        exit(1);
+    }
+
+    let tail_offset = offset_of!(RingBufferHeader, tail) as u64;
+
+    let mut pending_push: Option<ReadChunk<T>> = None;
+    let mut pending_credit = false;
+    // None until the head was written since the last connect
+    let mut sent_head = None;
+
+    loop {
+        if pending_push.is_none() {
+            let available = outbound_receiver.read();
+
+            let credit = ring_len - (available.start - state.peer_head.load_acquire());
+
+            let len = available.len().min(credit).min(message_size);
+
+            if len > 0 {
+                let chunk = ReadChunk {
+                    ring_buffer: available.ring_buffer,
+                    start: available.start,
+                    end: available.start + len,
+                };
+
+                let remote_offset = ((chunk.start % ring_len) * size_of::<T>()) as u64;
+
+                state.tail_staging[0] = chunk.end;
+
+                unsafe {
+                    ib_resource
+                        .post_write(
+                            DATA_WR_ID,
+                            mr,
+                            chunk.deref(),
+                            &remote_data,
+                            remote_offset,
+                            SendFlagBuilder::new().build(),
+                        )
+                        .expect("Failed to post data write");
+
+                    ib_resource
+                        .post_write(
+                            TAIL_WR_ID,
+                            mr,
+                            &state.tail_staging,
+                            &remote_indices,
+                            tail_offset,
+                            SendFlagBuilder::new().signaled().build(),
+                        )
+                        .expect("Failed to post tail write");
                 }
+
+                pending_push = Some(chunk);
             }
-        },
-        rdma_controller::config::ConnectionType::Client { message_size, .. } => loop {
-            if let Some(mut reader) = receiver.read_exact(message_size) {
-                assert_eq!(reader.len(), message_size);
-
-                // for val in reader.iter() {
-                //     if *val != expected_val {
-                //         eprintln!("Buffer: {:?}", reader);
-                //         panic!("");
-                //     }
-                //     expected_val = expected_val.wrapping_add(1);
-                // }
+        }
+
+        if !pending_credit {
+            let head = inbound_header.head.load_acquire();
+
+            if Some(head) != sent_head {
+                state.head_staging[0] = head;
 
                 unsafe {
                     ib_resource
-                        .post_send(2, &mut mr, reader.deref(), true)
-                        .expect("Failed to post send");
+                        .post_write(
+                            HEAD_WR_ID,
+                            mr,
+                            &state.head_staging,
+                            &remote_credit,
+                            0,
+                            SendFlagBuilder::new().signaled().build(),
+                        )
+                        .expect("Failed to post head write");
                 }
 
-                'polling: loop {
-                    for wc in ib_resource.poll_cq() {
-                        println!("Received work completion: {:?}", wc);
-                        if wc.status != rdma_sys::ibv_wc_status::IBV_WC_SUCCESS {
-                            panic!(
-                                "wc status {}, last error {}",
-                                wc.status,
-                                std::io::Error::last_os_error()
-                            );
-                        }
+                pending_credit = true;
+            }
+        }
 
-                        if wc.opcode == rdma_sys::ibv_wc_opcode::IBV_WC_SEND {
-                            break 'polling;
-                        }
+        let failed = poll_completions(ib_resource, |wc| match wc.wr_id {
+            TAIL_WR_ID => {
+                if let Some(mut chunk) = pending_push.take() {
+                    chunk.commit();
+                }
+            }
+            HEAD_WR_ID => {
+                sent_head = Some(state.head_staging[0]);
+                pending_credit = false;
+            }
+            _ => {}
+        });
+
+        if failed {
+            // Our tail in the peer's ring is the peer's inbound tail, which
+            // tells whether the write in flight landed
+            let peer_position = reconnect(ib_resource, inbound_header.tail.load_acquire());
+
+            if let Some(mut chunk) = pending_push.take() {
+                let delivered = peer_position.unwrap_or(chunk.start);
+
+                chunk.truncate(delivered.clamp(chunk.start, chunk.end) - chunk.start);
+                chunk.commit();
+            }
+
+            pending_credit = false;
+            sent_head = None;
+        }
+    }
+}
+
+// Pulls the remote outbound ring into our inbound ring: RDMA READ the remote
+// tail, RDMA READ whatever is new straight into the inbound ring, then RDMA
+// WRITE the remote head to hand the slots back. The remote adapter takes no
+// part in any of it, it only advertises its outbound ring.
+fn replicate_read<T: FromBytes + AsBytes + Copy + Send>(
+    ib_resource: &mut IbResource,
+    mr: &mut MemoryRegion,
+    inbound_sender: &Sender<T>,
+    state: &mut ReplicationState,
+    ring_len: usize,
+    message_size: usize,
+) {
+    const TAIL_READ_WR_ID: u64 = 7;
+    const DATA_READ_WR_ID: u64 = 8;
+    const HEAD_WR_ID: u64 = 9;
+
+    let remote_region = |name: &str| match ib_resource.remote_region(name) {
+        Some(region) => region.clone(),
+        None => {
+            eprintln!(
+                "Remote side did not advertise {}, is it in read mode?",
+                name
+            );
+            exit(1);
+        }
+    };
+
+    let remote_data = remote_region("outbound-data");
+    let remote_indices = remote_region("outbound-indices");
+
+    // Positions are mirrored, so both rings must have the same length
+    if remote_data.len != (ring_len * size_of::<T>()) as u64 {
+        eprintln!(
+            "Remote ring holds {} bytes, ours {}",
+            remote_data.len,
+            ring_len * size_of::<T>()
+        );
+        exit(1);
+    }
+
+    let head_offset = offset_of!(RingBufferHeader, head) as u64;
+    let tail_offset = offset_of!(RingBufferHeader, tail) as u64;
+
+    // Remote tail as of the last read, and how far we have pulled
+    let mut remote_tail = 0;
+    let mut pulled = 0;
+
+    let mut pending_tail = false;
+    let mut pending_pull: Option<(WriteChunk<T>, usize)> = None;
+    let mut pending_head = false;
+    // None until the head was written since the last connect
+    let mut sent_head = None;
+
+    loop {
+        if pending_pull.is_none() {
+            if remote_tail > pulled {
+                let to_end = ring_len - (pulled % ring_len);
+
+                let len = (remote_tail - pulled).min(to_end).min(message_size);
+
+                // Waits for the host to make room when the inbound ring is full
+                if let Some(mut chunk) = inbound_sender.try_reserve(len) {
+                    let remote_offset = ((pulled % ring_len) * size_of::<T>()) as u64;
+
+                    unsafe {
+                        ib_resource
+                            .post_read(
+                                DATA_READ_WR_ID,
+                                mr,
+                                Out::<'_, [T]>::from(chunk.deref_mut()),
+                                &remote_data,
+                                remote_offset,
+                                SendFlagBuilder::new().signaled().build(),
+                            )
+                            .expect("Failed to post data read");
                     }
+
+                    pending_pull = Some((chunk, len));
+                }
+            } else if !pending_tail {
+                unsafe {
+                    ib_resource
+                        .post_read(
+                            TAIL_READ_WR_ID,
+                            mr,
+                            Out::from(&mut state.tail_staging[..]),
+                            &remote_indices,
+                            tail_offset,
+                            SendFlagBuilder::new().signaled().build(),
+                        )
+                        .expect("Failed to post tail read");
                 }
 
-                reader.commit();
+                pending_tail = true;
             }
-        },
+        }
+
+        if !pending_head && Some(pulled) != sent_head {
+            state.head_staging[0] = pulled;
+
+            unsafe {
+                ib_resource
+                    .post_write(
+                        HEAD_WR_ID,
+                        mr,
+                        &state.head_staging,
+                        &remote_indices,
+                        head_offset,
+                        SendFlagBuilder::new().signaled().build(),
+                    )
+                    .expect("Failed to post head write");
+            }
+
+            pending_head = true;
+        }
+
+        let failed = poll_completions(ib_resource, |wc| match wc.wr_id {
+            TAIL_READ_WR_ID => {
+                remote_tail = state.tail_staging[0];
+                pending_tail = false;
+            }
+            DATA_READ_WR_ID => {
+                if let Some((mut chunk, len)) = pending_pull.take() {
+                    chunk.commit();
+                    pulled += len;
+                }
+            }
+            HEAD_WR_ID => {
+                sent_head = Some(state.head_staging[0]);
+                pending_head = false;
+            }
+            _ => {}
+        });
+
+        if failed {
+            // Reads have no side effects on the peer, whatever did not
+            // complete is simply read again
+            reconnect(ib_resource, pulled);
+
+            pending_tail = false;
+            pending_pull = None;
+            pending_head = false;
+            sent_head = None;
+        }
+    }
+}
+
+// Hands every successful completion to on_completion, without allocating.
+// Once a completion failed or the peer went away, the QP is flushed, whatever
+// succeeded before that is handed over as well and true returned. The caller
+// then resumes through `reconnect`.
+fn poll_completions(
+    ib_resource: &mut IbResource,
+    mut on_completion: impl FnMut(&WorkCompletion),
+) -> bool {
+    for wc in ib_resource.poll_cq_hybrid().expect("Failed to poll CQ") {
+        match wc.into_result() {
+            Ok(wc) => on_completion(&wc),
+            Err(err) => eprintln!("{}, reconnecting", err),
+        }
     }
+
+    if !ib_resource
+        .connection_lost()
+        .expect("Failed to check the connection")
+    {
+        return false;
+    }
+
+    match ib_resource.flush() {
+        Ok(flushed) => flushed.iter().for_each(on_completion),
+        Err(err) => {
+            eprintln!("Failed to flush the QP: {}", err);
+            exit(1);
+        }
+    }
+
+    true
+}
+
+// Connects again once the QP is flushed. The peer resumes its stream at
+// inbound_position, the returned position is how far the peer got in ours.
+fn reconnect(ib_resource: &mut IbResource, inbound_position: usize) -> Option<usize> {
+    ib_resource.set_resume_position(inbound_position as u64);
+
+    if let Err(err) = ib_resource.reconnect() {
+        eprintln!("Failed to reconnect: {}", err);
+        exit(1);
+    }
+
+    println!("Reconnected");
+
+    ib_resource
+        .peer_resume_position()
+        .map(|position| position as usize)
 }
@@ -1,6 +1,7 @@
 use std::num::{NonZeroU64, NonZeroUsize};
 
 use clap::{arg, command, Parser, Subcommand};
+use shared::ipc::{config::IpcEndpoint, element_type::ElementType};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -11,10 +12,19 @@ pub struct GlobalArgs {
     pub batch_size: NonZeroUsize,
     #[arg(global = true, short, long, default_value_t = NonZeroU64::new(5).unwrap())]
     pub duration: NonZeroU64,
+    #[arg(global = true, long)]
+    pub ipc_path: Option<IpcEndpoint>,
+    #[arg(global = true, long, default_value_t = 30)]
+    pub ipc_timeout: u64,
+    /// Ring element type, must match the adapter's --element: bytes, a primitive such as u64, or record:<size>:<align>:<name>
+    #[arg(global = true, long, default_value = "u64")]
+    pub element: ElementType,
 }
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum ConnectionType {
     Client,
     Server,
+    // Sends and receives at the same time over the session's ring pair
+    Duplex,
 }
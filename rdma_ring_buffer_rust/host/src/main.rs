@@ -1,9 +1,8 @@
 use std::{
+    fmt::Debug,
     fs::File,
-    io::{Read, Write},
     mem::{align_of, size_of, MaybeUninit},
-    slice,
-    sync::atomic::AtomicUsize,
+    thread,
     time::Duration,
 };
 
@@ -11,10 +10,12 @@ use clap::Parser;
 use quanta::Clock;
 use rand::random;
 use shared::{
-    ipc::{ring_buffer_metadata::RingBufferMetaData, Ipc},
-    ref_ring_buffer::RefRingBuffer,
+    client::{self, Receiver, Sender},
+    ipc::{
+        config::{IpcConfig, IpcEndpoint},
+        element_type::{ElementType, RingElement},
+    },
 };
-use shared_memory::ShmemConf;
 
 use crate::command_line::{ConnectionType, GlobalArgs};
 
@@ -31,53 +32,151 @@ fn main() {
 
     println!("Start Opening IPC");
 
-    let mut ipc = Ipc::open("sync");
-
-    println!("IPC Opened");
+    let ipc_config = IpcConfig {
+        endpoint: args.ipc_path.unwrap_or(IpcEndpoint::Default),
+        connect_timeout: Duration::from_secs(args.ipc_timeout),
+        ..Default::default()
+    };
 
-    let metadata = RingBufferMetaData::read_from(&mut ipc);
+    let element_type = args.element;
+
+    // Same layouts as the adapter, the slots are filled through a plain type
+    match (element_type.size, element_type.align) {
+        (1, 1) => run::<u8>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (2, 2) => run::<u16>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (4, 4) => run::<u32>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (8, 8) => run::<u64>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (16, 8) => run::<[u64; 2]>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (32, 8) => run::<[u64; 4]>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (64, 8) => run::<[u64; 8]>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (128, 8) => run::<[u64; 16]>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        (256, 8) => run::<[u64; 32]>(
+            &ipc_config,
+            element_type,
+            connection_type,
+            batch_size,
+            duration,
+        ),
+        // ElementType::from_str only accepts the layouts above
+        (size, align) => unreachable!("unsupported element layout {}/{}", size, align),
+    }
+}
 
-    println!("Ring Buffer Metadata: {:?}", metadata);
+fn run<T: TestElement>(
+    ipc_config: &IpcConfig,
+    element_type: ElementType,
+    connection_type: ConnectionType,
+    batch_size: usize,
+    duration: Duration,
+) {
+    let (sender, receiver, session) =
+        client::attach_as::<T>(ipc_config, element_type).expect("Failed to attach to adapter");
 
-    let shmem_os_id =
-        std::str::from_utf8(&metadata.shared_memory_name[..metadata.shared_memory_name_len])
-            .unwrap();
+    println!("Ring Buffer Metadata: {:?}", session.metadata());
 
-    let shmem = ShmemConf::new().os_id(shmem_os_id).open().unwrap();
+    println!("Shared Memory ID: {}", session.shmem_os_id());
 
-    println!("Shared Memory ID: {}", shmem.get_os_id());
+    println!("Starting RDMA Ring Buffer Test");
 
-    let shmem_ptr = shmem.as_ptr();
+    let dataflow = match connection_type {
+        ConnectionType::Server => consume(&receiver, duration),
+        ConnectionType::Client => produce(&sender, batch_size, duration),
+        ConnectionType::Duplex => thread::scope(|s| {
+            let producer = s.spawn(|| produce(&sender, batch_size, duration));
+            let consumed = consume(&receiver, duration);
 
-    let head_ref = unsafe {
-        AtomicUsize::from_ptr(
-            shmem_ptr
-                .byte_add(metadata.head_offset.try_into().unwrap())
-                .cast(),
-        )
-    };
-    let tail_ref = unsafe {
-        AtomicUsize::from_ptr(
-            shmem_ptr
-                .byte_add(metadata.tail_offset.try_into().unwrap())
-                .cast(),
-        )
+            consumed + producer.join().unwrap()
+        }),
     };
 
-    let mut ring_buffer = RefRingBuffer::<u64>::from_raw_parts(head_ref, tail_ref, unsafe {
-        slice::from_raw_parts_mut(
-            shmem_ptr
-                .byte_add(metadata.buffer_offset.try_into().unwrap())
-                .cast(),
-            metadata.ring_buffer_len,
-        )
-    });
+    println!("Process Data: {}", dataflow);
+    println!(
+        "Throughput: {} MB/s",
+        (dataflow * size_of::<T>()) as f64 / duration.as_secs_f64() / 1024.0 / 1024.0
+    );
 
-    let (sender, receiver) = ring_buffer.split();
+    session.detach().unwrap();
 
-    println!("Starting RDMA Ring Buffer Test");
-    let mut buffer = vec![0; batch_size];
+    println!("Finished RDMA Ring Buffer Test");
+}
 
+// Slots carry a running counter, truncated or repeated to fit the element
+trait TestElement: RingElement + PartialEq + Debug {
+    fn from_counter(counter: u64) -> Self;
+}
+
+macro_rules! impl_test_element {
+    ($($t:ty),*) => {
+        $(
+            impl TestElement for $t {
+                fn from_counter(counter: u64) -> Self {
+                    counter as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_test_element!(u8, u16, u32, u64);
+
+impl<const N: usize> TestElement for [u64; N]
+where
+    [u64; N]: RingElement,
+{
+    fn from_counter(counter: u64) -> Self {
+        [counter; N]
+    }
+}
+
+fn consume<T: TestElement>(receiver: &Receiver<T>, duration: Duration) -> usize {
     let clock = Clock::new();
 
     let begin = clock.now();
@@ -85,57 +184,64 @@ fn main() {
 
     let mut expected_data: u64 = 0;
 
-    match connection_type {
-        ConnectionType::Server => loop {
-            if clock.now() - begin > duration {
-                break;
+    loop {
+        if clock.now() - begin > duration {
+            break;
+        }
+
+        let mut chunk = receiver.read();
+
+        let reader_len = chunk.len();
+        dataflow += reader_len;
+
+        for data in chunk.iter() {
+            if *data != T::from_counter(expected_data) {
+                eprintln!("Reader {:?}", chunk);
+                panic!(
+                    "Data mismatch: expected {:?}, got {:?}",
+                    T::from_counter(expected_data),
+                    *data
+                );
             }
 
-            let mut chunk = receiver.read();
+            expected_data = expected_data.wrapping_add(1);
+        }
 
-            let reader_len = chunk.len();
-            dataflow += reader_len;
+        chunk.commit();
+    }
 
-            for data in chunk.iter() {
-                if *data != expected_data {
-                    eprintln!("Reader {:?}", chunk);
-                    panic!("Data mismatch: expected {}, got {}", expected_data, *data);
-                }
+    dataflow
+}
 
-                expected_data = expected_data.wrapping_add(1);
-            }
+fn produce<T: TestElement>(sender: &Sender<T>, batch_size: usize, duration: Duration) -> usize {
+    let mut buffer = vec![T::from_counter(0); batch_size];
 
-            chunk.commit();
-        },
-        ConnectionType::Client => 'outer: loop {
-            for val in buffer.iter_mut() {
-                *val = expected_data;
-                expected_data = expected_data.wrapping_add(1);
-                // println!("Write value: {}", buffer[i]);
-            }
+    let clock = Clock::new();
 
-            loop {
-                if clock.now() - begin > duration {
-                    break 'outer;
-                }
+    let begin = clock.now();
+    let mut dataflow = 0;
 
-                let write_len = sender.write(&mut buffer);
-                if write_len == batch_size {
-                    break;
-                }
-            }
+    let mut expected_data: u64 = 0;
 
-            dataflow += batch_size;
-        },
-    }
+    'outer: loop {
+        for val in buffer.iter_mut() {
+            *val = T::from_counter(expected_data);
+            expected_data = expected_data.wrapping_add(1);
+        }
 
-    println!("Process Data: {}", dataflow);
-    println!(
-        "Throughput: {} MB/s",
-        (dataflow * size_of::<u64>()) as f64 / duration.as_secs_f64() / 1024.0 / 1024.0
-    );
+        loop {
+            if clock.now() - begin > duration {
+                break 'outer;
+            }
+
+            let write_len = sender.write(&mut buffer);
+            if write_len == batch_size {
+                break;
+            }
+        }
 
-    ipc.write(&[1]).unwrap();
+        dataflow += batch_size;
+    }
 
-    println!("Finished RDMA Ring Buffer Test");
+    dataflow
 }
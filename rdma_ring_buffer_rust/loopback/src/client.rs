@@ -13,6 +13,8 @@ use shared::{
     atomic_extension::AtomicExtension,
     rdma_controller::{
         config::{self, Config},
+        send::SendFlagBuilder,
+        work_completion::WcOpcode,
         IbResource,
     },
     ref_ring_buffer::sender::Sender,
@@ -24,12 +26,19 @@ use crate::spec;
 pub fn connect_to_server(spec: spec::Spec, ready: &AtomicUsize) {
     let config = config::Config {
         dev_name: "mlx5_0".to_owned(),
+        port_num: 1,
         gid_index: Some(NonZeroI32::new(1).unwrap()),
         connection_type: shared::rdma_controller::config::ConnectionType::Client {
             port: spec.port,
             message_size: spec.message_size,
             server_addr: Ipv4Addr::LOCALHOST.into(),
         },
+        qp: Default::default(),
+        completion: Default::default(),
+        poll_batch: None,
+        recv_queue: Default::default(),
+        transport: Default::default(),
+        bootstrap: Default::default(),
     };
 
     let mut ring_buffer = RingBufferAlloc::<usize>::new(spec.buffer_size);
@@ -113,7 +122,12 @@ fn adapter(
         if let Some(mut reader) = receiver.read_exact(spec.message_size) {
             unsafe {
                 ib_resource
-                    .post_send(2, &mut mr, reader.deref(), true)
+                    .post_send(
+                        2,
+                        &mut mr,
+                        reader.deref(),
+                        SendFlagBuilder::new().signaled().build(),
+                    )
                     .expect("Failed to post send");
             }
 
@@ -122,18 +136,32 @@ fn adapter(
                     break 'outer;
                 }
 
-                for wc in ib_resource.poll_cq() {
-                    if wc.status != rdma_sys::ibv_wc_status::IBV_WC_SUCCESS {
-                        panic!(
-                            "wc status {}, last error {}",
-                            wc.status,
-                            std::io::Error::last_os_error()
-                        );
+                for wc in ib_resource.poll_cq_iter().expect("Failed to poll CQ") {
+                    if wc.is_success() && wc.opcode() == WcOpcode::Send {
+                        break 'polling;
                     }
+                }
+
+                if ib_resource
+                    .connection_lost()
+                    .expect("Failed to check the connection")
+                {
+                    ib_resource.flush().expect("Failed to flush QP");
 
-                    if wc.opcode == rdma_sys::ibv_wc_opcode::IBV_WC_SEND {
+                    ib_resource.reconnect().expect("Failed to reconnect");
+
+                    // The server tells how far it got, the message is only sent
+                    // again if it never arrived. Without a position it is sent
+                    // again, our send completing says nothing about the server.
+                    let delivered = ib_resource
+                        .peer_resume_position()
+                        .is_some_and(|position| position as usize >= reader.end);
+
+                    if delivered {
                         break 'polling;
                     }
+
+                    continue 'outer;
                 }
             }
 
@@ -15,6 +15,7 @@ use shared::{
     rdma_controller::{
         self,
         config::{self, Config},
+        work_completion::{WcOpcode, WorkCompletion},
         IbResource,
     },
     ring_buffer::{self, RingBufferAlloc},
@@ -23,14 +24,23 @@ use uninit::out_ref::Out;
 
 use crate::spec::{self, Spec};
 
+const RECV_WR_ID: u64 = 2;
+
 pub fn connect_to_client(spec: Spec, ready: &AtomicUsize) {
     let config = rdma_controller::config::Config {
         dev_name: "mlx5_0".to_owned(),
+        port_num: 1,
         gid_index: Some(NonZeroI32::new(1).unwrap()),
         connection_type: shared::rdma_controller::config::ConnectionType::Server {
             port: spec.port,
             message_size: spec.message_size,
         },
+        qp: Default::default(),
+        completion: Default::default(),
+        poll_batch: None,
+        recv_queue: Default::default(),
+        transport: Default::default(),
+        bootstrap: Default::default(),
     };
 
     let mut ring_buffer = RingBufferAlloc::<usize>::new(spec.buffer_size);
@@ -124,13 +134,18 @@ fn adapter(
         spin_loop();
     }
 
-    let mut expected_val = 0usize;
+    // Elements received so far, the client resumes here after a reconnect
+    let mut received = 0usize;
 
     'outer: loop {
         unsafe {
             if let Some(mut buffer) = sender.try_reserve(spec.message_size) {
                 ib_resource
-                    .post_recv(2, &mut mr, Out::<'_, [usize]>::from(buffer.deref_mut()))
+                    .post_recv(
+                        RECV_WR_ID,
+                        &mut mr,
+                        Out::<'_, [usize]>::from(buffer.deref_mut()),
+                    )
                     .expect("Failed to post recv");
 
                 'polling: loop {
@@ -138,23 +153,43 @@ fn adapter(
                         break 'outer;
                     }
 
-                    for wc in ib_resource.poll_cq() {
-                        if wc.status != rdma_sys::ibv_wc_status::IBV_WC_SUCCESS {
-                            panic!(
-                                "wc status {}, last error {}",
-                                wc.status,
-                                std::io::Error::last_os_error()
-                            );
+                    for wc in ib_resource.poll_cq_iter().expect("Failed to poll CQ") {
+                        if is_message(wc, buffer.len()) {
+                            break 'polling;
                         }
+                    }
 
-                        if wc.opcode == rdma_sys::ibv_wc_opcode::IBV_WC_RECV {
-                            break 'polling;
+                    if ib_resource
+                        .connection_lost()
+                        .expect("Failed to check the connection")
+                    {
+                        let flushed = ib_resource.flush().expect("Failed to flush QP");
+
+                        // The message landed before the QP failed
+                        if flushed.iter().any(|wc| is_message(wc, buffer.len())) {
+                            buffer.commit();
+                            received += buffer.len();
                         }
+
+                        ib_resource.set_resume_position(received as u64);
+
+                        ib_resource.reconnect().expect("Failed to reconnect");
+
+                        continue 'outer;
                     }
                 }
 
                 buffer.commit();
+                received += buffer.len();
             }
         }
     }
 }
+
+// Whether wc is our recv completing with a whole message of len elements
+fn is_message(wc: &WorkCompletion, len: usize) -> bool {
+    wc.is_success()
+        && wc.wr_id() == RECV_WR_ID
+        && wc.opcode() == WcOpcode::Recv
+        && wc.byte_len() as usize == len * size_of::<usize>()
+}
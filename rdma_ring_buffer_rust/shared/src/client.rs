@@ -0,0 +1,215 @@
+use std::{
+    io::{self, Write},
+    mem::{align_of, size_of, MaybeUninit},
+    ops::Deref,
+    ptr::slice_from_raw_parts_mut,
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use shared_memory::{Shmem, ShmemConf};
+
+use crate::{
+    ipc::{
+        config::IpcConfig,
+        element_type::{ElementType, RingElement},
+        ring_buffer_metadata::{RingBufferMetaData, RingDescriptor},
+        Ipc,
+    },
+    ref_ring_buffer::{receiver, sender, RefRingBuffer},
+};
+
+// Keeps the shared memory mapped for as long as any endpoint refers to it
+struct Mapping(Shmem);
+
+// Safety: the mapping is only touched through the ring buffer atomics and is
+// unmapped when the last reference goes away
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+pub struct Session {
+    ipc: Ipc,
+    metadata: RingBufferMetaData,
+    mapping: Arc<Mapping>,
+}
+
+impl Session {
+    pub fn metadata(&self) -> &RingBufferMetaData {
+        &self.metadata
+    }
+
+    pub fn shmem_os_id(&self) -> &str {
+        self.mapping.0.get_os_id()
+    }
+
+    // Tells the adapter we are done, it shuts down once the socket closes
+    pub fn detach(mut self) -> io::Result<()> {
+        self.ipc.write_all(&[1])
+    }
+}
+
+pub struct Sender<T: 'static> {
+    inner: sender::Sender<'static, T>,
+    _mapping: Arc<Mapping>,
+}
+
+impl<T: 'static> Deref for Sender<T> {
+    type Target = sender::Sender<'static, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+pub struct Receiver<T: 'static> {
+    inner: receiver::Receiver<'static, T>,
+    _mapping: Arc<Mapping>,
+}
+
+impl<T: 'static> Deref for Receiver<T> {
+    type Target = receiver::Receiver<'static, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+pub fn attach<T: RingElement>(config: &IpcConfig) -> io::Result<(Sender<T>, Receiver<T>, Session)> {
+    attach_as(config, T::ELEMENT_TYPE)
+}
+
+// Like attach, but negotiates element_type and accesses the slots as T, which
+// must have the same layout. Lets records named at runtime use a plain type.
+pub fn attach_as<T: RingElement>(
+    config: &IpcConfig,
+    element_type: ElementType,
+) -> io::Result<(Sender<T>, Receiver<T>, Session)> {
+    if element_type.size != size_of::<T>() as u64 || element_type.align != align_of::<T>() as u32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} does not have the layout of {}",
+                element_type,
+                T::ELEMENT_TYPE
+            ),
+        ));
+    }
+
+    let mut ipc = Ipc::open(config)?;
+
+    let metadata = RingBufferMetaData::read_from(&mut ipc)?;
+
+    if metadata.element_type != element_type {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "element type mismatch: adapter offers {}, host expects {}",
+                metadata.element_type, element_type
+            ),
+        ));
+    }
+
+    let shmem = ShmemConf::new()
+        .os_id(metadata.shared_memory_name()?)
+        .open()
+        .map_err(|err| io::Error::new(io::ErrorKind::NotFound, err.to_string()))?;
+
+    let outbound = ring_buffer_from_descriptor::<T>("outbound", &metadata.outbound, &shmem)?;
+    let inbound = ring_buffer_from_descriptor::<T>("inbound", &metadata.inbound, &shmem)?;
+
+    let mapping = Arc::new(Mapping(shmem));
+
+    // Safety: both halves hold the mapping alive, so 'static never outlives it
+    let (sender, receiver) = unsafe {
+        (
+            sender::Sender::from_owned(outbound),
+            receiver::Receiver::from_owned(inbound),
+        )
+    };
+
+    Ok((
+        Sender {
+            inner: sender,
+            _mapping: mapping.clone(),
+        },
+        Receiver {
+            inner: receiver,
+            _mapping: mapping.clone(),
+        },
+        Session {
+            ipc,
+            metadata,
+            mapping,
+        },
+    ))
+}
+
+fn ring_buffer_from_descriptor<T: Copy + Send>(
+    direction: &str,
+    descriptor: &RingDescriptor,
+    shmem: &Shmem,
+) -> io::Result<RefRingBuffer<T>> {
+    let invalid = |msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{direction} ring: {msg}"),
+        )
+    };
+
+    let shmem_len = shmem.len();
+
+    let check_word = |name: &str, offset: usize| {
+        if offset % align_of::<AtomicUsize>() != 0 {
+            return Err(invalid(format!("{name} offset {offset} is misaligned")));
+        }
+
+        match offset.checked_add(size_of::<AtomicUsize>()) {
+            Some(end) if end <= shmem_len => Ok(()),
+            _ => Err(invalid(format!(
+                "{name} offset {offset} is outside the {shmem_len} byte mapping"
+            ))),
+        }
+    };
+
+    check_word("head", descriptor.head_offset)?;
+    check_word("tail", descriptor.tail_offset)?;
+
+    if descriptor.ring_buffer_len == 0 {
+        return Err(invalid("ring buffer is empty".to_owned()));
+    }
+
+    if descriptor.buffer_offset % align_of::<T>() != 0 {
+        return Err(invalid(format!(
+            "buffer offset {} is misaligned for the element type",
+            descriptor.buffer_offset
+        )));
+    }
+
+    let buffer_end = descriptor
+        .ring_buffer_len
+        .checked_mul(size_of::<T>())
+        .and_then(|len| len.checked_add(descriptor.buffer_offset));
+
+    match buffer_end {
+        Some(end) if end <= shmem_len => {}
+        _ => {
+            return Err(invalid(format!(
+                "buffer of {} elements at offset {} does not fit the {} byte mapping",
+                descriptor.ring_buffer_len, descriptor.buffer_offset, shmem_len
+            )))
+        }
+    }
+
+    // Safety: every offset has been bounds and alignment checked above
+    unsafe {
+        let base = shmem.as_ptr();
+
+        let head = &*base.add(descriptor.head_offset).cast::<AtomicUsize>();
+        let tail = &*base.add(descriptor.tail_offset).cast::<AtomicUsize>();
+        let buffer = slice_from_raw_parts_mut(
+            base.add(descriptor.buffer_offset).cast::<MaybeUninit<T>>(),
+            descriptor.ring_buffer_len,
+        );
+
+        Ok(RefRingBuffer::from_raw_parts(head, tail, buffer))
+    }
+}
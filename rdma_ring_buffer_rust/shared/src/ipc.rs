@@ -1,40 +1,199 @@
 use std::{
-    fs::{self, File, OpenOptions},
-    io::{Read, Write},
-    os::unix::net::{SocketAddr, UnixListener, UnixStream},
-    path::Path,
+    fs::{self, Permissions},
+    io::{self, Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            fs::PermissionsExt,
+            net::{SocketAddr, UnixListener, UnixStream},
+        },
+    },
+    time::{Duration, Instant},
 };
 
+use nix::{
+    sys::socket::{getsockopt, sockopt::PeerCredentials as SoPeerCred},
+    unistd::Uid,
+};
+
+use self::config::{IpcConfig, IpcEndpoint, PeerCredentials};
+
+pub mod config;
+pub mod element_type;
 pub mod ring_buffer_metadata;
 
+const REPLY_ACCEPTED: u8 = 0;
+const REPLY_REFUSED: u8 = 1;
+
+// Longest refusal reason read from the adapter
+const MAX_REFUSAL_LEN: usize = 4096;
+
 pub struct Ipc {
     pipe: UnixStream,
 }
 
 impl Ipc {
-    pub fn create(path: impl AsRef<Path>) -> Ipc {
-        if path.as_ref().exists() {
-            fs::remove_file(path.as_ref()).unwrap();
+    pub fn create(config: &IpcConfig) -> io::Result<Ipc> {
+        let listener = match &config.endpoint {
+            IpcEndpoint::Abstract(name) => {
+                UnixListener::bind_addr(&SocketAddr::from_abstract_name(name.as_bytes())?)?
+            }
+            endpoint => {
+                let path = match endpoint {
+                    IpcEndpoint::Path(path) => path.clone(),
+                    _ => IpcEndpoint::default_path(),
+                };
+
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+
+                // Owner-only right after binding. The umask is process wide,
+                // so it is left alone, whoever connects in between is still
+                // subject to check_peer.
+                let listener = UnixListener::bind(&path)?;
+                fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+                listener
+            }
+        };
+
+        loop {
+            let (mut stream, _) = listener.accept()?;
+
+            let cred = peer_credentials(&stream)?;
+
+            if let Err(reason) = check_peer(config, &cred) {
+                eprintln!("Refused IPC peer {:?}: {}", cred, reason);
+
+                // The peer may already be gone, that is not our problem
+                let _ = write_refusal(&mut stream, &reason);
+                continue;
+            }
+
+            stream.write_all(&[REPLY_ACCEPTED])?;
+
+            return Ok(Ipc { pipe: stream });
         }
+    }
+
+    pub fn open(config: &IpcConfig) -> io::Result<Ipc> {
+        let addr = match &config.endpoint {
+            IpcEndpoint::Path(path) => SocketAddr::from_pathname(path)?,
+            IpcEndpoint::Abstract(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+            IpcEndpoint::Default => SocketAddr::from_pathname(IpcEndpoint::default_path())?,
+        };
+
+        let deadline = Instant::now() + config.connect_timeout;
 
-        let listener = UnixListener::bind(path).unwrap();
+        loop {
+            match UnixStream::connect_addr(&addr) {
+                Ok(stream) => return Self::read_reply(stream, deadline),
+                // The adapter has not bound the socket yet
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                    ) =>
+                {
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("timed out connecting to {:?}", config.endpoint),
+                        ));
+                    }
 
-        Ipc {
-            pipe: listener.accept().unwrap().0,
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(err) => return Err(err),
+            }
         }
     }
 
-    pub fn open(path: impl AsRef<Path>) -> Ipc {
-        while !path.as_ref().exists() {
-            std::thread::sleep(std::time::Duration::from_millis(1));
+    // The reply is due by the same deadline as the connect
+    fn read_reply(mut stream: UnixStream, deadline: Instant) -> io::Result<Ipc> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        // A zero timeout is refused, it would mean none at all
+        stream.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+
+        let mut reply = [0u8];
+
+        stream.read_exact(&mut reply).map_err(|err| {
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) {
+                io::Error::new(io::ErrorKind::TimedOut, "timed out waiting for the adapter")
+            } else {
+                err
+            }
+        })?;
+
+        match reply[0] {
+            REPLY_ACCEPTED => {
+                stream.set_read_timeout(None)?;
+
+                Ok(Ipc { pipe: stream })
+            }
+            REPLY_REFUSED => {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len)?;
+
+                // Only shown to the user, a longer reason is cut short
+                let len = (u32::from_le_bytes(len) as usize).min(MAX_REFUSAL_LEN);
+
+                let mut reason = vec![0u8; len];
+                stream.read_exact(&mut reason)?;
+
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "adapter refused connection: {}",
+                        String::from_utf8_lossy(&reason)
+                    ),
+                ))
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected IPC reply {other}"),
+            )),
         }
+    }
+}
 
-        let stream = UnixStream::connect(path).unwrap();
+fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let cred = getsockopt(stream, SoPeerCred)?;
+
+    Ok(PeerCredentials {
+        pid: cred.pid(),
+        uid: cred.uid(),
+        gid: cred.gid(),
+    })
+}
 
-        Ipc { pipe: stream }
+fn check_peer(config: &IpcConfig, cred: &PeerCredentials) -> Result<(), String> {
+    let allowed = if config.allowed_peers.is_empty() {
+        cred.uid == Uid::effective().as_raw()
+    } else {
+        config.allowed_peers.iter().any(|rule| rule.matches(cred))
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(format!(
+            "uid {} gid {} pid {} is not on the allow-list",
+            cred.uid, cred.gid, cred.pid
+        ))
     }
 }
 
+fn write_refusal(stream: &mut UnixStream, reason: &str) -> io::Result<()> {
+    stream.write_all(&[REPLY_REFUSED])?;
+    stream.write_all(&(reason.len() as u32).to_le_bytes())?;
+    stream.write_all(reason.as_bytes())
+}
+
 impl Read for Ipc {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         self.pipe.read(buf)
@@ -0,0 +1,96 @@
+use std::{convert::Infallible, env, path::PathBuf, str::FromStr, time::Duration};
+
+pub const DEFAULT_SOCKET_NAME: &str = "rdma_adapter.sock";
+
+pub struct IpcConfig {
+    pub endpoint: IpcEndpoint,
+    pub connect_timeout: Duration,
+    // Peers matching any rule may attach. An empty list only admits the
+    // effective uid of the creating process.
+    pub allowed_peers: Vec<PeerRule>,
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: IpcEndpoint::Default,
+            connect_timeout: Duration::from_secs(30),
+            allowed_peers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpcEndpoint {
+    // A socket file on the filesystem
+    Path(PathBuf),
+    // A socket in the Linux abstract namespace, no file is created
+    Abstract(String),
+    // `$XDG_RUNTIME_DIR/rdma_adapter.sock`, falling back to the temp dir
+    Default,
+}
+
+impl IpcEndpoint {
+    pub fn default_path() -> PathBuf {
+        env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir)
+            .join(DEFAULT_SOCKET_NAME)
+    }
+}
+
+// A leading `@` selects the abstract namespace, e.g. `@rdma_adapter`
+impl FromStr for IpcEndpoint {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix('@') {
+            Some(name) => IpcEndpoint::Abstract(name.to_owned()),
+            None => IpcEndpoint::Path(PathBuf::from(s)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerRule {
+    Uid(u32),
+    Gid(u32),
+    Pid(i32),
+}
+
+impl PeerRule {
+    pub fn matches(&self, cred: &PeerCredentials) -> bool {
+        match *self {
+            PeerRule::Uid(uid) => cred.uid == uid,
+            PeerRule::Gid(gid) => cred.gid == gid,
+            PeerRule::Pid(pid) => cred.pid == pid,
+        }
+    }
+}
+
+// Parses `uid:<n>`, `gid:<n>` or `pid:<n>`
+impl FromStr for PeerRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, id) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected uid:<n>, gid:<n> or pid:<n>, got {s:?}"))?;
+
+        let invalid = |_| format!("invalid id in peer rule {s:?}");
+
+        match kind {
+            "uid" => id.parse().map(PeerRule::Uid).map_err(invalid),
+            "gid" => id.parse().map(PeerRule::Gid).map_err(invalid),
+            "pid" => id.parse().map(PeerRule::Pid).map_err(invalid),
+            _ => Err(format!("unknown peer rule kind {kind:?}")),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fmt::Display,
+    mem::{align_of, size_of},
+    str::FromStr,
+};
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+// Describes what a ring slot holds so both ends of a session agree on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct ElementType {
+    pub kind: u32,
+    pub align: u32,
+    pub size: u64,
+    pub fingerprint: u64,
+}
+
+impl ElementType {
+    pub const KIND_BYTES: u32 = 0;
+    pub const KIND_RECORD: u32 = 1;
+
+    pub const BYTES: ElementType = ElementType {
+        kind: Self::KIND_BYTES,
+        align: 1,
+        size: 1,
+        fingerprint: 0,
+    };
+
+    pub const fn record<T>(name: &str) -> Self {
+        Self::record_with_layout(size_of::<T>(), align_of::<T>(), name)
+    }
+
+    pub const fn record_with_layout(size: usize, align: usize, name: &str) -> Self {
+        ElementType {
+            kind: Self::KIND_RECORD,
+            align: align as u32,
+            size: size as u64,
+            fingerprint: Self::fingerprint(name),
+        }
+    }
+
+    // Layouts the adapter can move: the 1, 2 and 4 byte primitives and power
+    // of two sizes from 8 to 256 bytes aligned to 8
+    pub const fn is_supported_layout(size: usize, align: usize) -> bool {
+        match (size, align) {
+            (1, 1) | (2, 2) | (4, 4) => true,
+            (8..=256, 8) => size.is_power_of_two(),
+            _ => false,
+        }
+    }
+
+    // FNV-1a of the type name, stable across builds and platforms
+    pub const fn fingerprint(name: &str) -> u64 {
+        let bytes = name.as_bytes();
+
+        let mut hash = 0xcbf29ce484222325u64;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            hash ^= bytes[i] as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+            i += 1;
+        }
+
+        hash
+    }
+}
+
+impl Display for ElementType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            Self::KIND_BYTES => write!(f, "bytes"),
+            Self::KIND_RECORD => write!(
+                f,
+                "record of {} bytes (align {}, fingerprint {:#018x})",
+                self.size, self.align, self.fingerprint
+            ),
+            kind => write!(f, "unknown element kind {}", kind),
+        }
+    }
+}
+
+// Parses `bytes`, a primitive such as `u64`, or `record:<size>:<align>:<name>`
+// with a layout the adapter supports
+impl FromStr for ElementType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" | "u8" => return Ok(u8::ELEMENT_TYPE),
+            "u16" => return Ok(u16::ELEMENT_TYPE),
+            "u32" => return Ok(u32::ELEMENT_TYPE),
+            "u64" => return Ok(u64::ELEMENT_TYPE),
+            "i16" => return Ok(i16::ELEMENT_TYPE),
+            "i32" => return Ok(i32::ELEMENT_TYPE),
+            "i64" => return Ok(i64::ELEMENT_TYPE),
+            "usize" => return Ok(usize::ELEMENT_TYPE),
+            "isize" => return Ok(isize::ELEMENT_TYPE),
+            _ => {}
+        }
+
+        let mut parts = s.splitn(4, ':');
+
+        let (Some("record"), Some(size), Some(align), Some(name)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!(
+                "expected bytes, a primitive or record:<size>:<align>:<name>, got {s:?}"
+            ));
+        };
+
+        let size: usize = size
+            .parse()
+            .map_err(|_| format!("invalid record size {size:?}"))?;
+        let align: usize = align
+            .parse()
+            .map_err(|_| format!("invalid record align {align:?}"))?;
+
+        if !ElementType::is_supported_layout(size, align) {
+            return Err(format!(
+                "unsupported record layout size {size} align {align}, supported are 1/1, 2/2, \
+                 4/4, and 8 to 256 byte power of two sizes with align 8"
+            ));
+        }
+
+        Ok(ElementType::record_with_layout(size, align, name))
+    }
+}
+
+// Element types that may be placed in a shared ring. Every bit pattern has to
+// be a valid value because the contents arrive from a remote peer.
+pub trait RingElement: FromBytes + AsBytes + Copy + Send + 'static {
+    const ELEMENT_TYPE: ElementType;
+}
+
+impl RingElement for u8 {
+    const ELEMENT_TYPE: ElementType = ElementType::BYTES;
+}
+
+macro_rules! impl_ring_element {
+    ($($t:ty),*) => {
+        $(
+            impl RingElement for $t {
+                const ELEMENT_TYPE: ElementType = ElementType::record::<$t>(stringify!($t));
+            }
+        )*
+    };
+}
+
+impl_ring_element!(u16, u32, u64, i16, i32, i64, usize, isize);
+
+// Stand-ins for records of the supported layouts, see client::attach_as
+impl_ring_element!([u64; 2], [u64; 4], [u64; 8], [u64; 16], [u64; 32]);
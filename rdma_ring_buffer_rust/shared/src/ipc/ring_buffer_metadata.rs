@@ -1,26 +1,57 @@
-use std::{io::Write, mem::MaybeUninit};
+use std::io::{self, Write};
 
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
-#[derive(Debug, FromZeroes, FromBytes, AsBytes)]
+use super::element_type::ElementType;
+
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
-pub struct RingBufferMetaData {
+pub struct RingDescriptor {
     pub head_offset: usize,
     pub tail_offset: usize,
     pub buffer_offset: usize,
     pub ring_buffer_len: usize,
+}
+
+// Directions are named from the host's point of view
+#[derive(Debug, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct RingBufferMetaData {
+    // Written by the host, drained by the adapter towards the remote side
+    pub outbound: RingDescriptor,
+    // Filled by the adapter from the remote side, read by the host
+    pub inbound: RingDescriptor,
+    // What both rings hold, checked by the host when it attaches
+    pub element_type: ElementType,
     pub shared_memory_name_len: usize,
     pub shared_memory_name: [u8; 32],
 }
 
 impl RingBufferMetaData {
-    pub fn write_to(&self, mut writer: impl Write) {
-        writer.write_all(zerocopy::AsBytes::as_bytes(self)).unwrap();
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(zerocopy::AsBytes::as_bytes(self))
     }
 
-    pub fn read_from(mut reader: impl std::io::Read) -> Self {
+    pub fn read_from(mut reader: impl std::io::Read) -> io::Result<Self> {
         let mut buffer = [0u8; std::mem::size_of::<Self>()];
-        reader.read_exact(&mut buffer).unwrap();
-        zerocopy::FromBytes::read_from(&buffer).unwrap()
+        reader.read_exact(&mut buffer)?;
+        Ok(zerocopy::FromBytes::read_from(&buffer).unwrap())
+    }
+
+    pub fn shared_memory_name(&self) -> io::Result<&str> {
+        let name = self
+            .shared_memory_name
+            .get(..self.shared_memory_name_len)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "shared memory name length {} is too long",
+                        self.shared_memory_name_len
+                    ),
+                )
+            })?;
+
+        std::str::from_utf8(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 }
@@ -1,4 +1,5 @@
 pub mod atomic_extension;
+pub mod client;
 pub mod ipc;
 pub mod rdma_controller;
 pub mod ref_ring_buffer;
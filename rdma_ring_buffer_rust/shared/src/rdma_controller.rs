@@ -1,12 +1,10 @@
 use bytemuck::{bytes_of, cast_ref, AnyBitPattern, NoUninit};
-use core::panic;
 use rand::random;
 use rdma_sys::{
     ibv_qp_state::{IBV_QPS_INIT, IBV_QPS_RESET},
     *,
 };
 use std::{
-    error::Error,
     ffi::CStr,
     fmt::Display,
     io::{self, Read, Write},
@@ -16,34 +14,99 @@ use std::{
     ops::{Range, RangeBounds},
     ptr::{copy_nonoverlapping, null_mut, read, slice_from_raw_parts, slice_from_raw_parts_mut},
     slice::{self, SliceIndex},
+    sync::Arc,
+    thread,
 };
 use uninit::out_ref::Out;
 use zerocopy::{AsBytes, FromBytes};
 
+pub use self::{
+    error::RdmaError,
+    recovery::{RECV_FLUSH_MARKER, SEND_FLUSH_MARKER},
+};
+
 use self::{
-    config::{Config, ConnectionType},
+    cm::CmConnection,
+    completion::{completion_buffer, DEFAULT_POLL_BATCH},
+    config::{
+        Bootstrap, CompletionMode, Config, ConnectionType, QpAttributes, RecvQueue, Transport,
+    },
     memory_region::MemoryRegion,
-    qp_info::DestQpInfo,
-    work_completion::WorkCompletion,
+    protection_domain::ProtectionDomain,
+    qp_info::{resume_position, resume_position_extension, DestQpInfo, Extension},
+    recovery::{CONNECT_ATTEMPTS, CONNECT_BACKOFF, CONNECT_MAX_BACKOFF},
+    remote_region::RemoteRegion,
+    send::{SendFlagBuilder, SendTracker, SgeList},
+    srq::SharedReceiveQueue,
+    ud::UdDestination,
+    work_completion::{WcOpcode, WorkCompletion},
 };
 
+pub mod async_event;
+mod cm;
+mod completion;
 pub mod config;
-mod qp_info;
+pub mod error;
+pub mod qp_info;
+mod recovery;
+pub mod remote_region;
 
 pub mod send;
+pub mod srq;
+pub mod ud;
 pub mod work_completion;
 
-mod memory_region;
+pub mod memory_region;
+mod protection_domain;
 
 pub struct IbResource {
     ctx: *mut ibv_context,
     pd: *mut ibv_pd,
+    // Owns ctx and pd once the PD is allocated
+    domain: Option<Arc<ProtectionDomain>>,
     mr: *mut ibv_mr,
     cq: *mut ibv_cq,
+    // Only created in CompletionMode::Hybrid
+    comp_channel: *mut ibv_comp_channel,
+    completion_mode: CompletionMode,
+    cq_armed: bool,
+    unacked_events: u32,
+    // poll_cq_iter and poll_cq_hybrid poll into this, sized by Config::poll_batch
+    wc_buffer: Box<[MaybeUninit<WorkCompletion>]>,
+    // Empty polls in a row, see poll_cq_hybrid
+    empty_polls: u32,
     qp: *mut ibv_qp,
-    // srq: *mut ibv_srq,
+    srq: Option<Arc<SharedReceiveQueue>>,
+    transport: Transport,
+    // Address of the peer a UD QP bootstrapped with in `connect`
+    ud_peer: Option<UdDestination>,
+    port_num: u8,
     port_attr: MaybeUninit<ibv_port_attr>,
     dev_attr: MaybeUninit<ibv_device_attr>,
+    // Resolved from the QpConfig once the port and device have been queried
+    qp_attrs: Option<QpAttributes>,
+    // Capabilities the QP was actually created with
+    qp_cap: ibv_qp_cap,
+    // PSN we start sending with, the peer was told it in our QP info
+    local_psn: u32,
+    send_tracker: SendTracker,
+    // Kept from the Config passed to `open` for `connect`
+    connection_type: Option<ConnectionType>,
+    bootstrap: Bootstrap,
+    gid_index: Option<NonZeroI32>,
+    // Set while connected through rdma_cm, disconnects on drop
+    cm: Option<CmConnection>,
+    // TCP bootstrap connection, kept open so either side notices the other
+    // one going away, see connection_lost
+    bootstrap_stream: Option<TcpStream>,
+    // Calls to connection_lost since the bootstrap link was last checked
+    liveness_polls: u32,
+    // Stream positions exchanged on connect, see set_resume_position
+    resume_position: Option<u64>,
+    peer_resume_position: Option<u64>,
+    // Regions we offer to the peer and the ones it offered to us
+    local_regions: Vec<RemoteRegion>,
+    remote_regions: Vec<RemoteRegion>,
     state: State,
 }
 
@@ -60,97 +123,172 @@ impl IbResource {
         IbResource {
             ctx: null_mut(),
             pd: null_mut(),
+            domain: None,
             mr: null_mut(),
             cq: null_mut(),
+            comp_channel: null_mut(),
+            completion_mode: CompletionMode::Polling,
+            cq_armed: false,
+            unacked_events: 0,
+            wc_buffer: completion_buffer(DEFAULT_POLL_BATCH),
+            empty_polls: 0,
             qp: null_mut(),
-            // srq: null_mut(),
+            srq: None,
+            transport: Transport::Rc,
+            ud_peer: None,
+            port_num: 1,
             port_attr: MaybeUninit::zeroed(),
             dev_attr: MaybeUninit::zeroed(),
+            qp_attrs: None,
+            qp_cap: unsafe { zeroed() },
+            local_psn: 0,
+            send_tracker: SendTracker::new(1),
+            connection_type: None,
+            bootstrap: Bootstrap::Tcp,
+            gid_index: None,
+            cm: None,
+            bootstrap_stream: None,
+            liveness_polls: 0,
+            resume_position: None,
+            peer_resume_position: None,
+            local_regions: vec![],
+            remote_regions: vec![],
             state: State::Init,
         }
     }
 
     pub fn setup_ib(&mut self, config: Config) -> Result<(), RdmaError> {
+        self.open(config)?;
+
+        self.connect()
+    }
+
+    // Opens the device and creates the PD, CQ and QP without connecting, so
+    // memory can be registered and advertised before `connect`
+    pub fn open(&mut self, config: Config) -> Result<(), RdmaError> {
+        self.connection_type = Some(config.connection_type);
+        self.gid_index = config.gid_index;
+        self.transport = config.transport;
+        self.bootstrap = config.bootstrap;
+
+        if self.bootstrap == Bootstrap::RdmaCm && self.transport != Transport::Rc {
+            return Err(RdmaError::InvalidQpConfig(
+                "rdma_cm bootstrap needs the RC transport".to_owned(),
+            ));
+        }
+
         unsafe {
-            let devices = ibv_get_device_list(null_mut());
+            if let RecvQueue::Shared(srq) = &config.recv_queue {
+                // QPs attached to an SRQ must live on its PD
+                self.ctx = srq.domain.ctx;
+                self.pd = srq.domain.pd;
+                self.domain = Some(srq.domain.clone());
+                self.srq = Some(srq.clone());
+            } else {
+                self.ctx = open_device(&config.dev_name)?;
+
+                self.pd = ibv_alloc_pd(self.ctx);
+
+                if self.pd.is_null() {
+                    return Err(RdmaError::AllocPdError(io::Error::last_os_error()));
+                }
 
-            if devices.is_null() {
-                return Err(RdmaError::GetIbDeviceError);
+                self.domain = Some(Arc::new(ProtectionDomain {
+                    ctx: self.ctx,
+                    pd: self.pd,
+                }));
             }
 
-            self.ctx = ibv_open_device(*devices);
-
             println!(
                 "ibv_open_device: {:?}",
-                CStr::from_ptr(
-                    self.ctx
-                        .as_ref()
-                        .unwrap()
-                        .device
-                        .as_ref()
-                        .unwrap()
-                        .name
-                        .as_ptr()
-                )
+                CStr::from_ptr(ibv_get_device_name((*self.ctx).device))
             );
 
-            if self.ctx.is_null() {
-                panic!("Failed to open IB device");
-            }
+            self.port_num = config.port_num;
 
-            self.pd = ibv_alloc_pd(self.ctx);
+            let ret = ibv_query_port(
+                self.ctx,
+                self.port_num,
+                self.port_attr.as_mut_ptr() as *mut _,
+            );
 
-            if self.pd.is_null() {
-                panic!("Failed to allocate protection domain");
+            if ret != 0 {
+                return Err(RdmaError::QueryPortError {
+                    port: self.port_num,
+                    source: errno(ret),
+                });
             }
 
-            let mut ret = ibv_query_port(self.ctx, 1, self.port_attr.as_mut_ptr() as *mut _);
+            let port_state = self.port_attr.assume_init_ref().state;
 
-            assert_eq!(
-                self.port_attr.assume_init().state,
-                ibv_port_state::IBV_PORT_ACTIVE
-            );
-
-            if ret != 0 {
-                panic!("Failed to query port");
+            if port_state != ibv_port_state::IBV_PORT_ACTIVE {
+                return Err(RdmaError::PortNotActive {
+                    port: self.port_num,
+                    state: port_state,
+                });
             }
 
-            ret = ibv_query_device(self.ctx, self.dev_attr.as_mut_ptr());
+            let ret = ibv_query_device(self.ctx, self.dev_attr.as_mut_ptr());
 
             if ret != 0 {
-                panic!("Failed to query device");
+                return Err(RdmaError::QueryDeviceError(errno(ret)));
             }
 
+            let qp_attrs = config.qp.resolve(
+                self.port_attr.assume_init_ref(),
+                self.dev_attr.assume_init_ref(),
+                config.gid_index,
+            )?;
+
+            println!("QP attributes: {:?}", qp_attrs);
+
+            self.qp_attrs = Some(qp_attrs);
+
             // create cq
 
+            self.completion_mode = config.completion;
+
+            self.wc_buffer = match config.poll_batch {
+                Some(0) => {
+                    return Err(RdmaError::InvalidQpConfig(
+                        "the poll batch must hold at least one completion".to_owned(),
+                    ))
+                }
+                Some(batch) => completion_buffer(batch),
+                None => completion_buffer(DEFAULT_POLL_BATCH),
+            };
+
+            if let CompletionMode::Hybrid { .. } = config.completion {
+                self.comp_channel = ibv_create_comp_channel(self.ctx);
+
+                if self.comp_channel.is_null() {
+                    return Err(RdmaError::CreateCompChannelError(io::Error::last_os_error()));
+                }
+            }
+
             self.cq = ibv_create_cq(
                 self.ctx,
                 self.dev_attr.assume_init_ref().max_cqe,
                 null_mut(),
-                null_mut(),
+                self.comp_channel,
                 0,
             );
 
             if self.cq.is_null() {
-                let os_error = std::io::Error::last_os_error();
-                println!("last OS error: {os_error:?}");
-                panic!("Failed to create completion queue");
+                return Err(RdmaError::CreateCqError(io::Error::last_os_error()));
             }
 
             // create srq
 
-            let mut ibv_srq_init_attr: ibv_srq_init_attr = zeroed();
+            if let RecvQueue::CreateShared(srq_config) = &config.recv_queue {
+                let srq_attrs = srq_config.resolve(self.dev_attr.assume_init_ref())?;
 
-            ibv_srq_init_attr.attr.max_wr = self.dev_attr.assume_init().max_srq_wr as u32;
-            ibv_srq_init_attr.attr.max_sge = 1;
+                println!("SRQ attributes: {:?}", srq_attrs);
 
-            // self.srq = ibv_create_srq(self.pd, &mut ibv_srq_init_attr);
+                let domain = self.domain.as_ref().unwrap();
 
-            // if self.srq.is_null() {
-            //     let os_error = std::io::Error::last_os_error();
-            //     println!("last OS error: {os_error:?}");
-            //     panic!("Failed to create shared receive queue");
-            // }
+                self.srq = Some(Arc::new(SharedReceiveQueue::create(domain, srq_attrs)?));
+            }
 
             // create qp
 
@@ -159,85 +297,110 @@ impl IbResource {
             println!("max_qp_wr: {}", self.dev_attr.assume_init().max_qp_wr);
 
             let mut qp_init_attr = ibv_qp_init_attr {
-                qp_type: ibv_qp_type::IBV_QPT_RC,
+                qp_type: match config.transport {
+                    Transport::Rc => ibv_qp_type::IBV_QPT_RC,
+                    Transport::Ud { .. } => ibv_qp_type::IBV_QPT_UD,
+                },
                 send_cq: self.cq,
                 recv_cq: self.cq,
-                // srq: self.srq,
+                srq: self.srq.as_ref().map_or(null_mut(), |srq| srq.as_ptr()),
                 cap: ibv_qp_cap {
-                    max_send_wr: 8192,
-                    max_recv_wr: 8192,
-                    max_send_sge: 3,
-                    max_recv_sge: 3,
-                    ..zeroed()
+                    max_send_wr: qp_attrs.max_send_wr,
+                    max_recv_wr: qp_attrs.max_recv_wr,
+                    max_send_sge: qp_attrs.max_send_sge,
+                    max_recv_sge: qp_attrs.max_recv_sge,
+                    max_inline_data: qp_attrs.inline_data_request(),
                 },
                 ..zeroed()
             };
 
             self.qp = ibv_create_qp(self.pd, &mut qp_init_attr);
 
-            if self.qp.is_null() {
-                let os_error = std::io::Error::last_os_error();
-                println!("last OS error: {os_error:?}");
-                panic!("Failed to create queue pair");
+            // The default inline size is only a wish, a device that cannot
+            // inline that much gets a QP without inline data instead
+            if self.qp.is_null()
+                && qp_attrs.max_inline_data.is_none()
+                && io::Error::last_os_error().raw_os_error()
+                    == Some(nix::errno::Errno::EINVAL as i32)
+            {
+                println!("Device refused inline data, creating the queue pair without");
+
+                qp_init_attr.cap.max_inline_data = 0;
+
+                self.qp = ibv_create_qp(self.pd, &mut qp_init_attr);
             }
 
-            println!("Max Inline Data: {}", qp_init_attr.cap.max_inline_data);
+            if self.qp.is_null() {
+                return Err(RdmaError::CreateQpError(io::Error::last_os_error()));
+            }
 
-            self.connect_dest(config).unwrap();
+            self.qp_cap = qp_init_attr.cap;
+            self.send_tracker = SendTracker::new(qp_attrs.signal_interval);
 
-            self.state = State::Connected;
+            println!("Max Inline Data: {}", qp_init_attr.cap.max_inline_data);
 
             Ok(())
         }
     }
 
+    // Exchanges QP info and the advertised regions with the peer and brings the QP to RTS
+    pub fn connect(&mut self) -> Result<(), RdmaError> {
+        let connection_type = self.connection_type.ok_or_else(|| {
+            RdmaError::BootstrapError(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "IB resources are not open",
+            ))
+        })?;
+
+        self.connect_dest(connection_type, self.gid_index)?;
+
+        self.state = State::Connected;
+
+        Ok(())
+    }
+
+    // Offers `region` to the peer on the next `connect`
+    pub fn advertise(&mut self, region: RemoteRegion) {
+        self.local_regions.retain(|other| other.name != region.name);
+        self.local_regions.push(region);
+    }
+
+    pub fn remote_regions(&self) -> &[RemoteRegion] {
+        &self.remote_regions
+    }
+
+    pub fn remote_region(&self, name: &str) -> Option<&RemoteRegion> {
+        self.remote_regions
+            .iter()
+            .find(|region| region.name == name)
+    }
+
     fn connect_qp_server(
         &mut self,
         port: u16,
         gid_index: Option<NonZeroI32>,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), RdmaError> {
         let socket_addr = SocketAddr::new(IpAddr::V4("0.0.0.0".parse().unwrap()), port);
 
-        let listener = TcpListener::bind(socket_addr)?;
-
-        let (mut stream, _) = listener.accept()?;
-
-        let buffer = &mut [0u8; size_of::<DestQpInfo>()];
+        let listener = TcpListener::bind(socket_addr).map_err(RdmaError::BootstrapError)?;
 
-        stream.read_exact(buffer)?;
+        let (mut stream, _) = listener.accept().map_err(RdmaError::BootstrapError)?;
 
-        let dest_info = unsafe { *(buffer.as_ptr() as *const DestQpInfo) };
+        let (dest_info, extensions) = DestQpInfo::read_from(&mut stream)?;
 
-        unsafe {
-            let mut gid: ibv_gid = zeroed();
+        let source_info = self.local_qp_info(gid_index)?;
 
-            if let Some(gid_index) = gid_index {
-                let ret = ibv_query_gid(self.ctx, 1, gid_index.get(), &mut gid);
-                if ret > 0 {
-                    panic!(
-                        "Could not get local gid for gid index {}\n",
-                        gid_index.get()
-                    );
-                }
-            }
+        source_info.write_to(&mut stream, &self.local_extensions())?;
 
-            let source_info = DestQpInfo {
-                lid: self.port_attr.assume_init().lid,
-                qpn: (*self.qp).qp_num,
-                psn: 0,
-                gid,
-            };
+        self.local_psn = source_info.psn;
 
-            stream.write(transmute::<&DestQpInfo, &[u8; size_of::<DestQpInfo>()]>(
-                &source_info,
-            ))?;
-        }
+        self.apply_peer_extensions(&extensions)?;
 
         println!("Received dest_info: {:?}", dest_info);
 
-        self.set_qp_rts(0, 1, dest_info)?;
+        self.bring_up(dest_info)?;
 
-        self.handshake();
+        self.bootstrap_stream = Some(stream);
 
         Ok(())
     }
@@ -247,65 +410,121 @@ impl IbResource {
         server_addr: IpAddr,
         port: u16,
         gid_index: Option<NonZeroI32>,
-    ) -> Result<(), Box<dyn Error>> {
-        unsafe {
-            let mut gid: ibv_gid = zeroed();
+    ) -> Result<(), RdmaError> {
+        let source_info = self.local_qp_info(gid_index)?;
 
-            if let Some(gid_index) = gid_index {
-                let ret = ibv_query_gid(self.ctx, 1, gid_index.get(), &mut gid);
-                if ret > 0 {
-                    panic!(
-                        "Could not get local gid for gid index {}\n",
-                        gid_index.get()
-                    );
-                }
-            }
+        println!("{:?}", source_info);
 
-            let source_info = DestQpInfo {
-                lid: self.port_attr.assume_init().lid,
-                qpn: (*self.qp).qp_num,
-                psn: 0,
-                gid: gid,
-            };
+        let socket_addr = SocketAddr::new(server_addr, port);
+
+        let mut stream = connect_retry(socket_addr).map_err(RdmaError::BootstrapError)?;
 
-            println!("{:?}", source_info);
+        source_info.write_to(&mut stream, &self.local_extensions())?;
 
-            let socket_addr = SocketAddr::new(server_addr, port);
+        self.local_psn = source_info.psn;
 
-            let mut stream = connect_retry(socket_addr);
+        let (dest_info, extensions) = DestQpInfo::read_from(&mut stream)?;
 
-            let buffer = transmute::<&DestQpInfo, &[u8; size_of::<DestQpInfo>()]>(&source_info);
+        self.apply_peer_extensions(&extensions)?;
 
-            stream.write_all(buffer).unwrap();
+        println!("Received {:?}", dest_info);
 
-            let buffer = &mut [0u8; size_of::<DestQpInfo>()];
+        self.bring_up(dest_info)?;
 
-            stream.read_exact(buffer).unwrap();
+        self.bootstrap_stream = Some(stream);
 
-            let dest_info = *(buffer.as_ptr() as *const DestQpInfo);
+        Ok(())
+    }
 
-            println!("Received {:?}", dest_info);
+    // RC QPs connect to the peer, UD QPs only need an address handle for it
+    fn bring_up(&mut self, dest_info: DestQpInfo) -> Result<(), RdmaError> {
+        match self.transport {
+            Transport::Rc => {
+                self.set_qp_rts(dest_info)?;
 
-            self.set_qp_rts(0, 1, dest_info)?;
+                self.handshake()
+            }
+            Transport::Ud { .. } => {
+                self.set_qp_ud_ready()?;
 
-            self.handshake();
+                self.ud_peer = Some(self.ud_destination(&dest_info)?);
+
+                Ok(())
+            }
         }
+    }
+
+    fn local_qp_info(&self, gid_index: Option<NonZeroI32>) -> Result<DestQpInfo, RdmaError> {
+        unsafe {
+            let mut gid: ibv_gid = zeroed();
+
+            if let Some(gid_index) = gid_index {
+                let ret = ibv_query_gid(self.ctx, self.port_num, gid_index.get(), &mut gid);
+
+                if ret != 0 {
+                    return Err(RdmaError::QueryGidError {
+                        port: self.port_num,
+                        gid_index: gid_index.get(),
+                        source: errno(ret),
+                    });
+                }
+            }
+
+            Ok(DestQpInfo::new(
+                self.port_attr.assume_init_ref().lid,
+                (*self.qp).qp_num,
+                gid,
+            ))
+        }
+    }
+
+    fn local_extensions(&self) -> Vec<Extension> {
+        self.local_regions
+            .iter()
+            .map(RemoteRegion::to_extension)
+            .chain(self.resume_position.map(resume_position_extension))
+            .collect()
+    }
+
+    fn apply_peer_extensions(&mut self, extensions: &[Extension]) -> Result<(), RdmaError> {
+        self.remote_regions =
+            RemoteRegion::from_extensions(extensions).map_err(RdmaError::InvalidQpInfo)?;
+
+        self.peer_resume_position =
+            resume_position(extensions).map_err(RdmaError::InvalidQpInfo)?;
 
         Ok(())
     }
 
-   
+    fn connect_dest(
+        &mut self,
+        connection_type: ConnectionType,
+        gid_index: Option<NonZeroI32>,
+    ) -> Result<(), RdmaError> {
+        if self.bootstrap == Bootstrap::RdmaCm {
+            return match connection_type {
+                ConnectionType::Server { port, .. } => self.connect_cm_server(port),
+                ConnectionType::Client {
+                    server_addr, port, ..
+                } => self.connect_cm_client(server_addr, port),
+            };
+        }
 
-    fn connect_dest(&mut self, config: Config) -> Result<(), Box<dyn Error>> {
-        match config.connection_type {
-            ConnectionType::Server { port, .. } => self.connect_qp_server(port, config.gid_index),
+        match connection_type {
+            ConnectionType::Server { port, .. } => self.connect_qp_server(port, gid_index),
             ConnectionType::Client {
                 server_addr, port, ..
-            } => self.connect_qp_client(server_addr, port, config.gid_index),
+            } => self.connect_qp_client(server_addr, port, gid_index),
         }
     }
 
-    pub fn set_qp_rts(&mut self, sl: u8, port: u8, dest: DestQpInfo) -> Result<(), Box<dyn Error>> {
+    pub fn set_qp_rts(&mut self, dest: DestQpInfo) -> Result<(), RdmaError> {
+        let port = self.port_num;
+
+        let attrs = self.qp_attrs.ok_or_else(|| {
+            RdmaError::InvalidQpConfig("QP attributes are not resolved yet".to_owned())
+        })?;
+
         unsafe {
             let mut qp_attr = ibv_qp_attr {
                 qp_state: IBV_QPS_RESET,
@@ -319,10 +538,11 @@ impl IbResource {
             );
 
             if ret != 0 {
-                panic!(
-                    "Failed to modify QP from RESET to INIT {}",
-                    std::io::Error::last_os_error()
-                );
+                return Err(RdmaError::ModifyQpError {
+                    from_state: "ANY",
+                    to_state: "RESET",
+                    source: errno(ret),
+                });
             }
 
             let mut qp_attr = ibv_qp_attr {
@@ -348,42 +568,24 @@ impl IbResource {
             );
 
             if ret != 0 {
-                panic!(
-                    "Failed to modify QP from RESET to INIT {}",
-                    std::io::Error::last_os_error()
-                );
-                return Err(Box::new(RdmaError::ModifyQpError {
+                return Err(RdmaError::ModifyQpError {
                     from_state: "RESET",
                     to_state: "INIT",
-                }));
+                    source: errno(ret),
+                });
             }
 
             let mut qp_attr = ibv_qp_attr {
                 qp_state: ibv_qp_state::IBV_QPS_RTR,
-                path_mtu: ibv_mtu::IBV_MTU_1024,
+                path_mtu: attrs.path_mtu,
                 dest_qp_num: dest.qpn,
                 rq_psn: dest.psn,
-                max_dest_rd_atomic: 1,
-                min_rnr_timer: 12,
-                ah_attr: ibv_ah_attr {
-                    is_global: 0,
-                    dlid: dest.lid,
-                    sl,
-                    src_path_bits: 0,
-                    port_num: port,
-                    ..zeroed()
-                },
+                max_dest_rd_atomic: attrs.max_dest_rd_atomic,
+                min_rnr_timer: attrs.min_rnr_timer,
+                ah_attr: self.ah_attr(&dest, &attrs),
                 ..zeroed()
             };
 
-            if dest.gid.global.interface_id != 0 {
-                qp_attr.ah_attr.is_global = 1;
-                qp_attr.ah_attr.grh.dgid = dest.gid;
-                qp_attr.ah_attr.grh.sgid_index = 0;
-                qp_attr.ah_attr.grh.hop_limit = 1;
-                qp_attr.ah_attr.grh.traffic_class = 0;
-            }
-
             let ret = ibv_modify_qp(
                 self.qp,
                 &mut qp_attr,
@@ -398,22 +600,19 @@ impl IbResource {
             );
 
             if ret != 0 {
-                panic!(
-                    "Failed to modify QP from INIT to RTR {}",
-                    std::io::Error::last_os_error()
-                );
-                return Err(Box::new(RdmaError::ModifyQpError {
+                return Err(RdmaError::ModifyQpError {
                     from_state: "INIT",
                     to_state: "RTR",
-                }));
+                    source: errno(ret),
+                });
             }
 
             qp_attr.qp_state = ibv_qp_state::IBV_QPS_RTS;
-            qp_attr.timeout = 14;
-            qp_attr.retry_cnt = 7;
-            qp_attr.rnr_retry = 7;
-            qp_attr.sq_psn = dest.psn;
-            qp_attr.max_rd_atomic = 1;
+            qp_attr.timeout = attrs.timeout;
+            qp_attr.retry_cnt = attrs.retry_cnt;
+            qp_attr.rnr_retry = attrs.rnr_retry;
+            qp_attr.sq_psn = self.local_psn;
+            qp_attr.max_rd_atomic = attrs.max_rd_atomic;
 
             let ret = ibv_modify_qp(
                 self.qp,
@@ -428,11 +627,11 @@ impl IbResource {
             );
 
             if ret != 0 {
-                panic!("Failed to modify QP from RTR to RTS");
-                return Err(Box::new(RdmaError::ModifyQpError {
+                return Err(RdmaError::ModifyQpError {
                     from_state: "RTR",
                     to_state: "RTS",
-                }));
+                    source: errno(ret),
+                });
             }
 
             // self.handshake();
@@ -443,34 +642,63 @@ impl IbResource {
         }
     }
 
-    fn handshake(&mut self) {
+    // Address vector towards dest, with a GRH whenever the peer has a GID
+    fn ah_attr(&self, dest: &DestQpInfo, attrs: &QpAttributes) -> ibv_ah_attr {
+        unsafe {
+            let mut ah_attr = ibv_ah_attr {
+                is_global: 0,
+                dlid: dest.lid,
+                sl: attrs.service_level,
+                src_path_bits: 0,
+                port_num: self.port_num,
+                ..zeroed()
+            };
+
+            if dest.gid.global.interface_id != 0 {
+                ah_attr.is_global = 1;
+                ah_attr.grh.dgid = dest.gid;
+                ah_attr.grh.sgid_index = attrs.sgid_index;
+                ah_attr.grh.hop_limit = attrs.hop_limit;
+                ah_attr.grh.traffic_class = attrs.traffic_class;
+            }
+
+            ah_attr
+        }
+    }
+
+    fn handshake(&mut self) -> Result<(), RdmaError> {
         const HANDSHAKE_WR_ID: u64 = 1;
 
         let mut buffer = Vec::<u8>::with_capacity(2);
         buffer.resize(2, 0);
 
-        let mut mr = self.register_memory_region(&mut buffer).unwrap();
+        let mut mr = self.register_memory_region(&mut buffer)?;
 
         unsafe {
             buffer[0] = random();
 
-            self.post_send(HANDSHAKE_WR_ID, &mut mr, &mut buffer[0..1], true)
-                .expect("Failed to post send on Handshake");
+            self.post_send(
+                HANDSHAKE_WR_ID,
+                &mut mr,
+                &mut buffer[0..1],
+                SendFlagBuilder::new().signaled().build(),
+            )?;
 
-            self.post_recv(HANDSHAKE_WR_ID, &mut mr, Out::from(&mut buffer[1..2]))
-                .expect("Failed to post recv on Handshake");
+            self.post_recv(HANDSHAKE_WR_ID, &mut mr, Out::from(&mut buffer[1..2]))?;
 
             let mut count = 0;
 
             loop {
-                for wc in self.poll_cq().iter() {
-                    if wc.wr_id == HANDSHAKE_WR_ID {
+                for wc in self.poll_cq()? {
+                    if wc.wr_id() == HANDSHAKE_WR_ID {
+                        let wc = wc.into_result()?;
+
                         count += 1;
-                        if wc.opcode == ibv_wc_opcode::IBV_WC_RECV {
+                        if wc.opcode() == WcOpcode::Recv {
                             println!("Received data: {:?}", buffer[1]);
                         }
 
-                        if wc.opcode == ibv_wc_opcode::IBV_WC_SEND {
+                        if wc.opcode() == WcOpcode::Send {
                             println!("Sent data: {:?}", buffer[0]);
                         }
 
@@ -479,7 +707,7 @@ impl IbResource {
                         if count >= 2 {
                             println!("Handshake done");
 
-                            return;
+                            return Ok(());
                         }
                     }
                 }
@@ -487,29 +715,49 @@ impl IbResource {
         }
     }
 
-    pub fn poll_cq(&mut self) -> Vec<WorkCompletion> {
-        unsafe {
-            const WC_INIT: MaybeUninit<ibv_wc> = MaybeUninit::zeroed();
+    // Allocates the result, poll_cq_into and poll_cq_iter don't
+    pub fn poll_cq(&mut self) -> Result<Vec<WorkCompletion>, RdmaError> {
+        let mut completions = Vec::with_capacity(self.wc_buffer.len());
 
-            let mut wc_buffer = [WC_INIT; 16];
+        let polled = self.poll_cq_into(completions.spare_capacity_mut())?.len();
 
-            let num_polled = ibv_poll_cq(self.cq, 16, &mut wc_buffer as *mut _ as *mut _);
+        unsafe { completions.set_len(polled) };
 
-            if num_polled < 0 {
-                panic!("Failed to poll cq");
-            }
+        Ok(completions)
+    }
 
-            if num_polled == 0 {
-                return vec![];
-            }
+    // Polls up to buffer.len() completions into buffer and returns them
+    pub fn poll_cq_into<'a>(
+        &mut self,
+        buffer: &'a mut [MaybeUninit<WorkCompletion>],
+    ) -> Result<&'a [WorkCompletion], RdmaError> {
+        let capacity = buffer.len().min(i32::MAX as usize) as i32;
+
+        // WorkCompletion is a transparent ibv_wc
+        let num_polled = unsafe { ibv_poll_cq(self.cq, capacity, buffer.as_mut_ptr().cast()) };
 
-            // println!("Polled {} wc", num_polled);
+        if num_polled < 0 {
+            return Err(RdmaError::PollCqError(num_polled));
+        }
 
-            wc_buffer[..num_polled as usize]
-                .iter_mut()
-                .map(|wc| WorkCompletion::from(wc.assume_init_read()))
-                .collect()
+        let completions = unsafe {
+            slice::from_raw_parts(
+                buffer.as_ptr().cast::<WorkCompletion>(),
+                num_polled as usize,
+            )
+        };
+
+        // Successful send side completions free their slots in the send queue,
+        // any failure has moved the QP to the error state
+        for wc in completions {
+            if !wc.is_success() {
+                self.state = State::Error;
+            } else if !wc.opcode().is_recv() {
+                self.send_tracker.on_completion();
+            }
         }
+
+        Ok(completions)
     }
 
     pub unsafe fn post_recv<'a, T: FromBytes>(
@@ -517,81 +765,140 @@ impl IbResource {
         wr_id: u64,
         mr: &mut MemoryRegion,
         buffer: Out<'a, [T]>,
-    ) -> io::Result<()> {
-        unsafe {
-            let mut bad_recv_wr = null_mut();
+    ) -> Result<(), RdmaError> {
+        self.post_recv_sg(wr_id, [(&*mr, buffer)])
+    }
 
-            let lkey = mr.mr.as_ref().unwrap().lkey;
+    // Receives one message scattered over several buffers, in order. Empty
+    // buffers are left out of the SGE list.
+    // Safety: every buffer must be part of the memory region it is paired with
+    pub unsafe fn post_recv_sg<'a, T: FromBytes + 'a>(
+        &mut self,
+        wr_id: u64,
+        segments: impl IntoIterator<Item = (&'a MemoryRegion, Out<'a, [T]>)>,
+    ) -> Result<(), RdmaError> {
+        let mut list = SgeList::collect(
+            wr_id,
+            segments.into_iter().map(|(mr, buffer)| {
+                let mut pointer = buffer.as_bytes_out();
+
+                ibv_sge {
+                    addr: pointer.as_mut_ptr() as *mut u8 as u64,
+                    length: pointer.len().try_into().unwrap(),
+                    lkey: mr.lkey,
+                }
+            }),
+            self.max_recv_sge(),
+        )?;
 
-            let mut pointer = buffer.as_bytes_out();
+        let list = list.as_mut_slice();
 
-            let mut list = ibv_sge {
-                addr: pointer.as_mut_ptr() as *mut u8 as u64,
-                length: pointer.len().try_into().unwrap(),
-                lkey,
-            };
+        unsafe {
+            let mut bad_recv_wr = null_mut();
 
             let mut recv_wr = ibv_recv_wr {
                 wr_id,
-                sg_list: &mut list,
-                num_sge: 1,
+                sg_list: list.as_mut_ptr(),
+                num_sge: list.len() as i32,
                 ..zeroed()
             };
 
-            let errno = ibv_post_recv(self.qp, &mut recv_wr, &mut bad_recv_wr);
+            let ret = self.post_recv_wrs(&mut recv_wr, &mut bad_recv_wr);
 
-            if errno != 0 {
-                return Err(io::Error::last_os_error());
+            if ret != 0 {
+                return Err(RdmaError::PostRecvError {
+                    wr_id,
+                    source: errno(ret),
+                });
             }
 
             return Ok(());
         }
     }
+}
+
+// Teardown runs in reverse order of setup: the QP is moved to the error state
+// so outstanding WRs are flushed, the flushed completions are drained, then
+// the QP and the CQ are destroyed. The PD and the device context go last,
+// once every MemoryRegion registered on them has been dropped as well.
+impl Drop for IbResource {
+    fn drop(&mut self) {
+        // Disconnect before the QP is torn down under the CM
+        self.cm = None;
+
+        unsafe {
+            if !self.qp.is_null() {
+                let mut qp_attr = ibv_qp_attr {
+                    qp_state: ibv_qp_state::IBV_QPS_ERR,
+                    ..zeroed()
+                };
 
-    // pub unsafe fn post_srq_recv<'a, T: FromBytes>(
-    //     &mut self,
-    //     wr_id: u64,
-    //     mr: &mut MemoryRegion,
-    //     buffer: Out<'a, [T]>,
-    // ) -> io::Result<()> {
-    //     unsafe {
-    //         let mut bad_recv_wr = null_mut();
-
-    //         let lkey = mr.mr.as_ref().unwrap().lkey;
-
-    //         let buffer_len = buffer.len();
-
-    //         let mut pointer = buffer.as_bytes_out();
-
-    //         println!(
-    //             "Posting SRQ recv with buffer: {:?} and length: {} with data length {}",
-    //             pointer,
-    //             pointer.len(),
-    //             buffer_len
-    //         );
-
-    //         let mut list = ibv_sge {
-    //             addr: pointer.as_mut_ptr() as *mut u8 as u64,
-    //             length: pointer.len().try_into().unwrap(),
-    //             lkey,
-    //         };
-
-    //         let mut recv_wr = ibv_recv_wr {
-    //             wr_id,
-    //             sg_list: &mut list,
-    //             num_sge: 1,
-    //             ..zeroed()
-    //         };
-
-    //         let errno = ibv_post_srq_recv(self.srq, &mut recv_wr, &mut bad_recv_wr);
-
-    //         if errno != 0 {
-    //             return Err(io::Error::last_os_error());
-    //         }
-
-    //         return Ok(());
-    //     }
-    // }
+                let ret = ibv_modify_qp(
+                    self.qp,
+                    &mut qp_attr,
+                    (ibv_qp_attr_mask::IBV_QP_STATE).0 as i32,
+                );
+
+                if ret != 0 {
+                    eprintln!("Failed to move QP to the error state: {}", errno(ret));
+                }
+
+                if !self.cq.is_null() {
+                    self.drain_cq();
+                }
+
+                let ret = ibv_destroy_qp(self.qp);
+
+                if ret != 0 {
+                    eprintln!("Failed to destroy queue pair: {}", errno(ret));
+                }
+
+                self.qp = null_mut();
+            }
+
+            // The SRQ goes once no QP of ours is attached to it anymore
+            self.srq = None;
+
+            if !self.cq.is_null() {
+                self.ack_cq_events();
+
+                let ret = ibv_destroy_cq(self.cq);
+
+                if ret != 0 {
+                    eprintln!("Failed to destroy completion queue: {}", errno(ret));
+                }
+
+                self.cq = null_mut();
+            }
+
+            if !self.comp_channel.is_null() {
+                let ret = ibv_destroy_comp_channel(self.comp_channel);
+
+                if ret != 0 {
+                    eprintln!("Failed to destroy completion channel: {}", errno(ret));
+                }
+
+                self.comp_channel = null_mut();
+            }
+
+            // Without a domain nothing owns the context yet
+            if self.domain.take().is_none() && !self.ctx.is_null() {
+                ibv_close_device(self.ctx);
+            }
+
+            self.pd = null_mut();
+            self.ctx = null_mut();
+        }
+    }
+}
+
+impl IbResource {
+    // Discards the flushed WRs of a QP in error
+    fn drain_cq(&mut self) {
+        if let Err(err) = self.drain_flushed(|_| {}) {
+            eprintln!("Failed to drain the completion queue: {}", err);
+        }
+    }
 }
 
 pub struct RdmaHandShake {
@@ -603,68 +910,84 @@ pub enum ConnectionError {
     TcpStreamError,
 }
 
-#[derive(Debug)]
-pub enum RdmaError {
-    GetIbDeviceError,
-    OpenIbDeviceError,
-    AllocPdError,
-    QueryPortError(i32),
-    QueryDeviceError(i32),
-    CreateCqError,
-    CreateSrqError,
-    ModifyQpError {
-        from_state: &'static str,
-        to_state: &'static str,
-    },
-    RegMrError,
-    CreateQpError,
-}
 // const IBV_ACCESS_RELAXED_ORDERING: i32 = IBV_ACCESS_OPTIONAL_FIRST;
 
-impl Error for RdmaError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
-    }
+// Opens the device called `name`, the device list is released either way
+fn open_device(name: &str) -> Result<*mut ibv_context, RdmaError> {
+    unsafe {
+        let mut num_devices = 0;
 
-    fn description(&self) -> &str {
-        "description() is deprecated; use Display"
-    }
+        let devices = ibv_get_device_list(&mut num_devices);
 
-    fn cause(&self) -> Option<&dyn Error> {
-        self.source()
+        if devices.is_null() {
+            return Err(RdmaError::GetIbDeviceError(io::Error::last_os_error()));
+        }
+
+        let devices_slice = slice::from_raw_parts(devices, num_devices.max(0) as usize);
+
+        let names: Vec<String> = devices_slice
+            .iter()
+            .map(|&device| {
+                CStr::from_ptr(ibv_get_device_name(device))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+
+        let result = match names.iter().position(|device_name| device_name == name) {
+            Some(index) => {
+                let ctx = ibv_open_device(devices_slice[index]);
+
+                if ctx.is_null() {
+                    Err(RdmaError::OpenIbDeviceError {
+                        name: name.to_owned(),
+                        source: io::Error::last_os_error(),
+                    })
+                } else {
+                    Ok(ctx)
+                }
+            }
+            None => Err(RdmaError::DeviceNotFound {
+                name: name.to_owned(),
+                available: names,
+            }),
+        };
+
+        ibv_free_device_list(devices);
+
+        result
     }
 }
 
-impl Display for RdmaError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            RdmaError::GetIbDeviceError => write!(f, "Failed to get IB device list"),
-            RdmaError::OpenIbDeviceError => write!(f, "Failed to open IB device"),
-            RdmaError::AllocPdError => write!(f, "Failed to allocate protection domain"),
-            RdmaError::QueryPortError(ret) => {
-                write!(f, "Failed to query port with return value {}", ret)
-            }
-            RdmaError::QueryDeviceError(ret) => {
-                write!(f, "Failed to query device with return value {}", ret)
-            }
-            RdmaError::CreateCqError => write!(f, "Failed to create completion queue"),
-            RdmaError::CreateQpError => write!(f, "Failed to create queue pair"),
-            RdmaError::CreateSrqError => write!(f, "Failed to create shared receive queue"),
-            RdmaError::ModifyQpError {
-                from_state,
-                to_state,
-            } => write!(f, "Failed to modify QP from {} to {}", from_state, to_state),
-            RdmaError::RegMrError => write!(f, "Failed to register memory region"),
-        }
+// Verbs either return the errno directly or return -1 and set errno
+pub(crate) fn errno(ret: i32) -> io::Error {
+    if ret > 0 {
+        io::Error::from_raw_os_error(ret)
+    } else {
+        io::Error::last_os_error()
     }
 }
 
-fn connect_retry(ipport: SocketAddr) -> TcpStream {
+// Waits for the server to start listening, backing off between attempts like
+// reconnect does for rdma_cm. Any other error, or still being refused after
+// CONNECT_ATTEMPTS, is returned.
+fn connect_retry(ipport: SocketAddr) -> io::Result<TcpStream> {
+    let mut attempts = 1;
+    let mut backoff = CONNECT_BACKOFF;
+
     loop {
-        let stream = TcpStream::connect(ipport);
-        match stream {
-            Ok(stream) => return stream,
-            Err(_) => continue,
+        match TcpStream::connect(ipport) {
+            Ok(stream) => return Ok(stream),
+            Err(err)
+                if err.kind() == io::ErrorKind::ConnectionRefused
+                    && attempts < CONNECT_ATTEMPTS =>
+            {
+                thread::sleep(backoff);
+
+                attempts += 1;
+                backoff = (backoff * 2).min(CONNECT_MAX_BACKOFF);
+            }
+            Err(err) => return Err(err),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,79 @@
+use std::{io, os::fd::RawFd};
+
+use rdma_sys::*;
+
+use super::{IbResource, RdmaError};
+
+// Events the device reports outside of the CQ. QP events carry the QP number
+// and SRQ events the SRQ handle, see SharedReceiveQueue::handle, since every
+// resource sharing a device context sees the events of all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsyncEvent {
+    CqError,
+    QpFatal { qp_num: u32 },
+    QpRequestError { qp_num: u32 },
+    QpAccessError { qp_num: u32 },
+    CommEstablished { qp_num: u32 },
+    QpLastWqeReached { qp_num: u32 },
+    SrqLimitReached { srq_handle: u32 },
+    SrqError { srq_handle: u32 },
+    PortActive { port: u8 },
+    PortError { port: u8 },
+    DeviceFatal,
+    Other(ibv_event_type::Type),
+}
+
+impl IbResource {
+    // Fd of the device context's async event queue, readable once an event is
+    // pending. It can be added to an epoll set next to the completion fd.
+    pub fn async_fd(&self) -> RawFd {
+        unsafe { (*self.ctx).async_fd }
+    }
+
+    // Takes the next async event, blocks until there is one
+    pub fn get_async_event(&self) -> Result<AsyncEvent, RdmaError> {
+        unsafe {
+            let mut event: ibv_async_event = std::mem::zeroed();
+
+            if ibv_get_async_event(self.ctx, &mut event) != 0 {
+                return Err(RdmaError::GetAsyncEventError(io::Error::last_os_error()));
+            }
+
+            let qp_num = || (*event.element.qp).qp_num;
+            let srq_handle = || (*event.element.srq).handle;
+            let port = || event.element.port_num as u8;
+
+            let async_event = match event.event_type {
+                ibv_event_type::IBV_EVENT_CQ_ERR => AsyncEvent::CqError,
+                ibv_event_type::IBV_EVENT_QP_FATAL => AsyncEvent::QpFatal { qp_num: qp_num() },
+                ibv_event_type::IBV_EVENT_QP_REQ_ERR => {
+                    AsyncEvent::QpRequestError { qp_num: qp_num() }
+                }
+                ibv_event_type::IBV_EVENT_QP_ACCESS_ERR => {
+                    AsyncEvent::QpAccessError { qp_num: qp_num() }
+                }
+                ibv_event_type::IBV_EVENT_COMM_EST => {
+                    AsyncEvent::CommEstablished { qp_num: qp_num() }
+                }
+                ibv_event_type::IBV_EVENT_QP_LAST_WQE_REACHED => {
+                    AsyncEvent::QpLastWqeReached { qp_num: qp_num() }
+                }
+                ibv_event_type::IBV_EVENT_SRQ_LIMIT_REACHED => AsyncEvent::SrqLimitReached {
+                    srq_handle: srq_handle(),
+                },
+                ibv_event_type::IBV_EVENT_SRQ_ERR => AsyncEvent::SrqError {
+                    srq_handle: srq_handle(),
+                },
+                ibv_event_type::IBV_EVENT_PORT_ACTIVE => AsyncEvent::PortActive { port: port() },
+                ibv_event_type::IBV_EVENT_PORT_ERR => AsyncEvent::PortError { port: port() },
+                ibv_event_type::IBV_EVENT_DEVICE_FATAL => AsyncEvent::DeviceFatal,
+                other => AsyncEvent::Other(other),
+            };
+
+            // Unacknowledged events block destroying the element they refer to
+            ibv_ack_async_event(&mut event);
+
+            Ok(async_event)
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    ffi::CStr,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::fd::RawFd,
+    ptr::null_mut,
+    slice,
+};
+
+use nix::sys::socket::{SockaddrLike, SockaddrStorage};
+use rdma_sys::*;
+use uninit::out_ref::Out;
+
+use super::{
+    memory_region::MemoryRegion,
+    qp_info::{DestQpInfo, QpInfoError, MAX_MESSAGE_LEN, MIN_MESSAGE_LEN},
+    send::SendFlagBuilder,
+    IbResource, RdmaError,
+};
+
+// Private data of the connect request and reply: magic "RDCM" and the length
+// of the QP info message its sender posts once connected, both big-endian.
+// Advertised regions don't fit the 56 bytes a request may carry, so they
+// travel in that message over the connected QP instead.
+const CM_MAGIC: u32 = u32::from_be_bytes(*b"RDCM");
+const PRIVATE_DATA_LEN: usize = 8;
+
+const RESOLVE_TIMEOUT_MS: i32 = 2000;
+
+const CM_EXCHANGE_WR_ID: u64 = 1;
+
+// The CM ids of a connection made through rdma_cm. Our QP is not created
+// through the id, rdma_cm only resolves the path and supplies the attributes
+// for each state transition.
+pub(crate) struct CmConnection {
+    channel: *mut rdma_event_channel,
+    id: *mut rdma_cm_id,
+    listen_id: *mut rdma_cm_id,
+}
+
+impl CmConnection {
+    fn new() -> Result<Self, RdmaError> {
+        let channel = unsafe { rdma_create_event_channel() };
+
+        if channel.is_null() {
+            return Err(RdmaError::CmError {
+                op: "rdma_create_event_channel",
+                source: io::Error::last_os_error(),
+            });
+        }
+
+        Ok(CmConnection {
+            channel,
+            id: null_mut(),
+            listen_id: null_mut(),
+        })
+    }
+
+    // Readable once the CM has an event for us. Past ESTABLISHED that can only
+    // mean the connection is going away.
+    pub(super) fn event_fd(&self) -> RawFd {
+        unsafe { (*self.channel).fd }
+    }
+
+    fn create_id(&self) -> Result<*mut rdma_cm_id, RdmaError> {
+        let mut id = null_mut();
+
+        cm_call("rdma_create_id", unsafe {
+            rdma_create_id(
+                self.channel,
+                &mut id,
+                null_mut(),
+                rdma_port_space::RDMA_PS_TCP,
+            )
+        })?;
+
+        Ok(id)
+    }
+
+    // Waits for the next event, anything but `expected` is an error
+    fn expect_event(&self, expected: rdma_cm_event_type::Type) -> Result<CmEvent, RdmaError> {
+        let event = unsafe {
+            let mut event = null_mut();
+
+            cm_call(
+                "rdma_get_cm_event",
+                rdma_get_cm_event(self.channel, &mut event),
+            )?;
+
+            // Private data lives in the event, copy it before acknowledging
+            let conn = (*event).param.conn;
+
+            let private_data = if conn.private_data.is_null() {
+                vec![]
+            } else {
+                slice::from_raw_parts(
+                    conn.private_data as *const u8,
+                    conn.private_data_len as usize,
+                )
+                .to_vec()
+            };
+
+            let copied = CmEvent {
+                id: (*event).id,
+                event: (*event).event,
+                status: (*event).status,
+                conn,
+                private_data,
+            };
+
+            rdma_ack_cm_event(event);
+
+            copied
+        };
+
+        // Nobody listens on the port (yet)
+        if event.event == rdma_cm_event_type::RDMA_CM_EVENT_REJECTED {
+            return Err(RdmaError::CmRejected {
+                status: event.status,
+            });
+        }
+
+        if event.event != expected {
+            // A request we won't take is turned down instead of left hanging
+            if event.event == rdma_cm_event_type::RDMA_CM_EVENT_CONNECT_REQUEST {
+                unsafe {
+                    rdma_reject(event.id, null_mut(), 0);
+                    rdma_destroy_id(event.id);
+                }
+            }
+
+            return Err(RdmaError::CmUnexpectedEvent {
+                expected: event_str(expected),
+                received: event_str(event.event),
+                status: event.status,
+            });
+        }
+
+        Ok(event)
+    }
+}
+
+impl Drop for CmConnection {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.id.is_null() {
+                rdma_disconnect(self.id);
+
+                if rdma_destroy_id(self.id) != 0 {
+                    eprintln!("Failed to destroy CM id: {}", io::Error::last_os_error());
+                }
+            }
+
+            if !self.listen_id.is_null() && rdma_destroy_id(self.listen_id) != 0 {
+                eprintln!(
+                    "Failed to destroy listening CM id: {}",
+                    io::Error::last_os_error()
+                );
+            }
+
+            rdma_destroy_event_channel(self.channel);
+        }
+    }
+}
+
+struct CmEvent {
+    id: *mut rdma_cm_id,
+    event: rdma_cm_event_type::Type,
+    status: i32,
+    // private_data points into the acknowledged event, use the copy below
+    conn: rdma_conn_param,
+    private_data: Vec<u8>,
+}
+
+impl IbResource {
+    pub(super) fn connect_cm_client(
+        &mut self,
+        server_addr: IpAddr,
+        port: u16,
+    ) -> Result<(), RdmaError> {
+        let mut cm = CmConnection::new()?;
+
+        cm.id = cm.create_id()?;
+
+        let dest = SockaddrStorage::from(SocketAddr::new(server_addr, port));
+
+        cm_call("rdma_resolve_addr", unsafe {
+            rdma_resolve_addr(
+                cm.id,
+                null_mut(),
+                dest.as_ptr() as *mut sockaddr,
+                RESOLVE_TIMEOUT_MS,
+            )
+        })?;
+
+        cm.expect_event(rdma_cm_event_type::RDMA_CM_EVENT_ADDR_RESOLVED)?;
+
+        self.check_cm_device(cm.id)?;
+
+        cm_call("rdma_resolve_route", unsafe {
+            rdma_resolve_route(cm.id, RESOLVE_TIMEOUT_MS)
+        })?;
+
+        cm.expect_event(rdma_cm_event_type::RDMA_CM_EVENT_ROUTE_RESOLVED)?;
+
+        self.modify_qp_cm(cm.id, ibv_qp_state::IBV_QPS_INIT)?;
+
+        let local_info = self.local_info_message()?;
+
+        let private_data = private_data(local_info.len());
+
+        let mut conn_param = self.conn_param(&private_data);
+
+        cm_call("rdma_connect", unsafe {
+            rdma_connect(cm.id, &mut conn_param)
+        })?;
+
+        let reply = cm.expect_event(rdma_cm_event_type::RDMA_CM_EVENT_CONNECT_RESPONSE)?;
+
+        let remote_len = parse_private_data(&reply.private_data)?;
+
+        let mut buffer = vec![0u8; local_info.len() + remote_len];
+
+        let mut mr = self.register_memory_region(&mut buffer)?;
+
+        unsafe {
+            self.post_recv(
+                CM_EXCHANGE_WR_ID,
+                &mut mr,
+                Out::from(&mut buffer[local_info.len()..]),
+            )?;
+        }
+
+        self.modify_qp_cm(cm.id, ibv_qp_state::IBV_QPS_RTR)?;
+        self.modify_qp_cm(cm.id, ibv_qp_state::IBV_QPS_RTS)?;
+
+        cm_call("rdma_establish", unsafe { rdma_establish(cm.id) })?;
+
+        self.cm = Some(cm);
+
+        buffer[..local_info.len()].copy_from_slice(&local_info);
+
+        self.exchange_info_cm(&mut mr, &buffer, local_info.len())
+    }
+
+    pub(super) fn connect_cm_server(&mut self, port: u16) -> Result<(), RdmaError> {
+        let mut cm = CmConnection::new()?;
+
+        cm.listen_id = cm.create_id()?;
+
+        let addr = SockaddrStorage::from(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+
+        cm_call("rdma_bind_addr", unsafe {
+            rdma_bind_addr(cm.listen_id, addr.as_ptr() as *mut sockaddr)
+        })?;
+
+        cm_call("rdma_listen", unsafe { rdma_listen(cm.listen_id, 1) })?;
+
+        let request = cm.expect_event(rdma_cm_event_type::RDMA_CM_EVENT_CONNECT_REQUEST)?;
+
+        cm.id = request.id;
+
+        // Turned down on failure, the client would wait for an answer otherwise
+        let (local_info, mut buffer, mut mr) = match self.prepare_cm_accept(cm.id, &request) {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                unsafe { rdma_reject(cm.id, null_mut(), 0) };
+
+                return Err(err);
+            }
+        };
+
+        let private_data = private_data(local_info.len());
+
+        let mut conn_param = self.conn_param(&private_data);
+
+        // Never grant more RDMA reads than the client can issue, or issue more
+        // than it accepts
+        conn_param.responder_resources = conn_param
+            .responder_resources
+            .min(request.conn.initiator_depth);
+        conn_param.initiator_depth = conn_param
+            .initiator_depth
+            .min(request.conn.responder_resources);
+
+        cm_call("rdma_accept", unsafe {
+            rdma_accept(cm.id, &mut conn_param)
+        })?;
+
+        cm.expect_event(rdma_cm_event_type::RDMA_CM_EVENT_ESTABLISHED)?;
+
+        self.cm = Some(cm);
+
+        buffer[..local_info.len()].copy_from_slice(&local_info);
+
+        self.exchange_info_cm(&mut mr, &buffer, local_info.len())
+    }
+
+    // Everything the server does with a connect request before accepting it.
+    // Returns our QP info and the exchange buffer with the recv for the
+    // client's info already posted.
+    fn prepare_cm_accept(
+        &mut self,
+        id: *mut rdma_cm_id,
+        request: &CmEvent,
+    ) -> Result<(Vec<u8>, Vec<u8>, MemoryRegion), RdmaError> {
+        self.check_cm_device(id)?;
+
+        let remote_len = parse_private_data(&request.private_data)?;
+
+        self.modify_qp_cm(id, ibv_qp_state::IBV_QPS_INIT)?;
+        self.modify_qp_cm(id, ibv_qp_state::IBV_QPS_RTR)?;
+
+        let local_info = self.local_info_message()?;
+
+        let mut buffer = vec![0u8; local_info.len() + remote_len];
+
+        let mut mr = self.register_memory_region(&mut buffer)?;
+
+        // The client may send as soon as we accept
+        unsafe {
+            self.post_recv(
+                CM_EXCHANGE_WR_ID,
+                &mut mr,
+                Out::from(&mut buffer[local_info.len()..]),
+            )?;
+        }
+
+        self.modify_qp_cm(id, ibv_qp_state::IBV_QPS_RTS)?;
+
+        Ok((local_info, buffer, mr))
+    }
+
+    // rdma_cm picks the device from the address, it has to be the one our
+    // PD, CQ and QP live on
+    fn check_cm_device(&self, id: *mut rdma_cm_id) -> Result<(), RdmaError> {
+        unsafe {
+            let expected = CStr::from_ptr(ibv_get_device_name((*self.ctx).device))
+                .to_string_lossy()
+                .into_owned();
+
+            let found = CStr::from_ptr(ibv_get_device_name((*(*id).verbs).device))
+                .to_string_lossy()
+                .into_owned();
+
+            if expected != found || (*id).port_num != self.port_num {
+                return Err(RdmaError::CmDeviceMismatch {
+                    expected: format!("{}:{}", expected, self.port_num),
+                    found: format!("{}:{}", found, (*id).port_num),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Moves the QP to `state` with the attributes rdma_cm resolved: GID, path
+    // MTU, PSNs and the rest of the path come from the CM, not from QpConfig
+    fn modify_qp_cm(
+        &mut self,
+        id: *mut rdma_cm_id,
+        state: ibv_qp_state::Type,
+    ) -> Result<(), RdmaError> {
+        let (from_state, to_state) = match state {
+            ibv_qp_state::IBV_QPS_INIT => ("RESET", "INIT"),
+            ibv_qp_state::IBV_QPS_RTR => ("INIT", "RTR"),
+            _ => ("RTR", "RTS"),
+        };
+
+        unsafe {
+            let mut qp_attr = ibv_qp_attr {
+                qp_state: state,
+                ..std::mem::zeroed()
+            };
+
+            let mut mask = 0;
+
+            cm_call(
+                "rdma_init_qp_attr",
+                rdma_init_qp_attr(id, &mut qp_attr, &mut mask),
+            )?;
+
+            // rdma_cm leaves out atomics, the ring regions may be their target
+            if state == ibv_qp_state::IBV_QPS_INIT {
+                qp_attr.qp_access_flags |= ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC.0;
+            }
+
+            let ret = ibv_modify_qp(self.qp, &mut qp_attr, mask);
+
+            if ret != 0 {
+                return Err(RdmaError::ModifyQpError {
+                    from_state,
+                    to_state,
+                    source: super::errno(ret),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn conn_param(&self, private_data: &[u8; PRIVATE_DATA_LEN]) -> rdma_conn_param {
+        let attrs = self.qp_attrs.unwrap();
+
+        rdma_conn_param {
+            private_data: private_data.as_ptr() as *const _,
+            private_data_len: PRIVATE_DATA_LEN as u8,
+            responder_resources: attrs.max_dest_rd_atomic,
+            initiator_depth: attrs.max_rd_atomic,
+            flow_control: 1,
+            retry_count: attrs.retry_cnt,
+            rnr_retry_count: attrs.rnr_retry,
+            srq: self.srq.is_some() as u8,
+            qp_num: unsafe { (*self.qp).qp_num },
+        }
+    }
+
+    fn local_info_message(&self) -> Result<Vec<u8>, RdmaError> {
+        self.local_qp_info(self.gid_index)?
+            .encode(&self.local_extensions())
+            .map_err(RdmaError::InvalidQpInfo)
+    }
+
+    // Sends our QP info with the advertised regions and waits for the peer's,
+    // whose recv is already posted right behind ours in buffer
+    fn exchange_info_cm(
+        &mut self,
+        mr: &mut MemoryRegion,
+        buffer: &[u8],
+        local_len: usize,
+    ) -> Result<(), RdmaError> {
+        unsafe {
+            self.post_send(
+                CM_EXCHANGE_WR_ID,
+                mr,
+                &buffer[..local_len],
+                SendFlagBuilder::new().signaled().build(),
+            )?;
+        }
+
+        let mut sent = false;
+        let mut received = false;
+
+        while !(sent && received) {
+            for wc in self.poll_cq()? {
+                if wc.wr_id() != CM_EXCHANGE_WR_ID {
+                    continue;
+                }
+
+                if wc.into_result()?.opcode().is_recv() {
+                    received = true;
+                } else {
+                    sent = true;
+                }
+            }
+        }
+
+        let (dest_info, extensions) =
+            DestQpInfo::decode(&buffer[local_len..]).map_err(RdmaError::InvalidQpInfo)?;
+
+        println!("Received {:?}", dest_info);
+
+        self.apply_peer_extensions(&extensions)
+    }
+}
+
+fn private_data(info_len: usize) -> [u8; PRIVATE_DATA_LEN] {
+    let mut data = [0u8; PRIVATE_DATA_LEN];
+
+    data[0..4].copy_from_slice(&CM_MAGIC.to_be_bytes());
+    data[4..8].copy_from_slice(&(info_len as u32).to_be_bytes());
+
+    data
+}
+
+// Length of the QP info message the peer is going to send. It sizes a buffer
+// we register, so it is held to what the QP info codec accepts.
+fn parse_private_data(data: &[u8]) -> Result<usize, RdmaError> {
+    let invalid = |msg: &str| {
+        RdmaError::BootstrapError(io::Error::new(io::ErrorKind::InvalidData, msg.to_owned()))
+    };
+
+    // The CM may pad private data, only the start of it is ours
+    let data = data
+        .get(..PRIVATE_DATA_LEN)
+        .ok_or_else(|| invalid("CM private data is too short"))?;
+
+    if u32::from_be_bytes(data[0..4].try_into().unwrap()) != CM_MAGIC {
+        return Err(invalid("CM private data has a bad magic"));
+    }
+
+    let len = u32::from_be_bytes(data[4..8].try_into().unwrap());
+
+    if !(MIN_MESSAGE_LEN..=MAX_MESSAGE_LEN).contains(&(len as usize)) {
+        return Err(RdmaError::InvalidQpInfo(QpInfoError::BadLength(len)));
+    }
+
+    Ok(len as usize)
+}
+
+// librdmacm returns -1 and leaves the cause in errno
+fn cm_call(op: &'static str, ret: i32) -> Result<(), RdmaError> {
+    if ret != 0 {
+        return Err(RdmaError::CmError {
+            op,
+            source: io::Error::last_os_error(),
+        });
+    }
+
+    Ok(())
+}
+
+fn event_str(event: rdma_cm_event_type::Type) -> String {
+    unsafe {
+        CStr::from_ptr(rdma_event_str(event))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    io,
+    mem::{take, MaybeUninit},
+    os::fd::{BorrowedFd, RawFd},
+    ptr::null_mut,
+    slice,
+    time::Duration,
+};
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use rdma_sys::*;
+
+use super::{
+    config::CompletionMode, errno, work_completion::WorkCompletion, IbResource, RdmaError,
+};
+
+// Events are acknowledged in batches, ibv_ack_cq_events takes a lock
+const ACK_BATCH: u32 = 64;
+
+// Completions taken per poll unless Config::poll_batch says otherwise
+pub(super) const DEFAULT_POLL_BATCH: usize = 16;
+
+pub(super) fn completion_buffer(len: usize) -> Box<[MaybeUninit<WorkCompletion>]> {
+    (0..len).map(|_| MaybeUninit::uninit()).collect()
+}
+
+impl IbResource {
+    // Fd of the completion channel, readable once an armed CQ got a completion.
+    // It can be added to an epoll set next to other event sources.
+    pub fn completion_fd(&self) -> Option<RawFd> {
+        if self.comp_channel.is_null() {
+            None
+        } else {
+            Some(unsafe { (*self.comp_channel).fd })
+        }
+    }
+
+    // Arms the CQ, the next completion (or the next solicited one) raises an
+    // event on the completion channel. Completions already in the CQ don't,
+    // so poll once more after arming.
+    pub fn req_notify(&mut self, solicited_only: bool) -> Result<(), RdmaError> {
+        if self.comp_channel.is_null() {
+            return Err(RdmaError::NoCompletionChannel);
+        }
+
+        let ret = unsafe { ibv_req_notify_cq(self.cq, solicited_only as i32) };
+
+        if ret != 0 {
+            return Err(RdmaError::ReqNotifyCqError(errno(ret)));
+        }
+
+        self.cq_armed = true;
+
+        Ok(())
+    }
+
+    // Consumes one event from the completion channel, blocks until there is one.
+    // The CQ is disarmed afterwards.
+    pub fn get_cq_event(&mut self) -> Result<(), RdmaError> {
+        if self.comp_channel.is_null() {
+            return Err(RdmaError::NoCompletionChannel);
+        }
+
+        unsafe {
+            let mut cq = null_mut();
+            let mut cq_context = null_mut();
+
+            if ibv_get_cq_event(self.comp_channel, &mut cq, &mut cq_context) != 0 {
+                return Err(RdmaError::GetCqEventError(io::Error::last_os_error()));
+            }
+        }
+
+        self.cq_armed = false;
+        self.unacked_events += 1;
+
+        if self.unacked_events >= ACK_BATCH {
+            self.ack_cq_events();
+        }
+
+        Ok(())
+    }
+
+    // One poll into the buffer IbResource keeps for it, nothing is allocated.
+    // The completions borrow the resource, they have to be handled before the
+    // next post.
+    pub fn poll_cq_iter(&mut self) -> Result<slice::Iter<'_, WorkCompletion>, RdmaError> {
+        let polled = self.poll_cq_buffered()?;
+
+        Ok(self.polled(polled).iter())
+    }
+
+    // Polls while completions keep coming. In hybrid mode, once idle_polls
+    // polls in a row came back empty, the CQ is armed and the channel fd
+    // waited on for up to max_wait before polling again. An empty result only
+    // means nothing completed, the caller may have other work to check.
+    pub fn poll_cq_hybrid(&mut self) -> Result<&[WorkCompletion], RdmaError> {
+        let polled = self.poll_cq_hybrid_buffered()?;
+
+        Ok(self.polled(polled))
+    }
+
+    fn poll_cq_hybrid_buffered(&mut self) -> Result<usize, RdmaError> {
+        let polled = self.poll_cq_buffered()?;
+
+        let CompletionMode::Hybrid {
+            idle_polls,
+            max_wait,
+        } = self.completion_mode
+        else {
+            return Ok(polled);
+        };
+
+        if polled > 0 {
+            self.empty_polls = 0;
+
+            return Ok(polled);
+        }
+
+        self.empty_polls = self.empty_polls.saturating_add(1);
+
+        if self.empty_polls < idle_polls {
+            return Ok(polled);
+        }
+
+        if !self.cq_armed {
+            self.req_notify(false)?;
+
+            // Anything that completed before arming raises no event
+            let polled = self.poll_cq_buffered()?;
+
+            if polled > 0 {
+                self.empty_polls = 0;
+
+                return Ok(polled);
+            }
+        }
+
+        if self.wait_completion_fd(max_wait)? {
+            self.get_cq_event()?;
+            self.empty_polls = 0;
+        }
+
+        self.poll_cq_buffered()
+    }
+
+    // Polls into wc_buffer, the count is for `polled`
+    pub(super) fn poll_cq_buffered(&mut self) -> Result<usize, RdmaError> {
+        let mut buffer = take(&mut self.wc_buffer);
+
+        let polled = self
+            .poll_cq_into(&mut buffer)
+            .map(|completions| completions.len());
+
+        self.wc_buffer = buffer;
+
+        polled
+    }
+
+    // The first `polled` completions in wc_buffer, as written by poll_cq_buffered
+    pub(super) fn polled(&self, polled: usize) -> &[WorkCompletion] {
+        unsafe { slice::from_raw_parts(self.wc_buffer.as_ptr().cast(), polled) }
+    }
+
+    // Whether the channel fd became readable within timeout
+    fn wait_completion_fd(&self, timeout: Duration) -> Result<bool, RdmaError> {
+        let fd = unsafe { BorrowedFd::borrow_raw((*self.comp_channel).fd) };
+
+        // poll counts whole milliseconds, rounding down would turn a wait
+        // shorter than one into none at all
+        let millis = timeout.as_nanos().div_ceil(1_000_000);
+
+        let timeout = PollTimeout::try_from(millis).unwrap_or(PollTimeout::MAX);
+
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+
+        match poll(&mut fds, timeout) {
+            Ok(ready) => Ok(ready > 0),
+            Err(nix::errno::Errno::EINTR) => Ok(false),
+            Err(err) => Err(RdmaError::GetCqEventError(err.into())),
+        }
+    }
+
+    // Every event must be acknowledged before the CQ can be destroyed
+    pub(super) fn ack_cq_events(&mut self) {
+        if self.unacked_events > 0 {
+            unsafe { ibv_ack_cq_events(self.cq, self.unacked_events) };
+
+            self.unacked_events = 0;
+        }
+    }
+}
@@ -1,9 +1,140 @@
-use std::{net::IpAddr, num::NonZeroI32};
+use std::{net::IpAddr, num::NonZeroI32, sync::Arc, time::Duration};
+
+use rdma_sys::{ibv_device_attr, ibv_mtu, ibv_port_attr};
+
+use super::{srq::SharedReceiveQueue, RdmaError};
 
 pub struct Config {
     pub dev_name: String,
+    pub port_num: u8,
     pub gid_index: Option<NonZeroI32>,
     pub connection_type: ConnectionType,
+    pub qp: QpConfig,
+    pub completion: CompletionMode,
+    // Completions taken per poll by poll_cq, poll_cq_iter and poll_cq_hybrid, defaults to 16
+    pub poll_batch: Option<usize>,
+    pub recv_queue: RecvQueue,
+    pub transport: Transport,
+    pub bootstrap: Bootstrap,
+}
+
+// How the peers find each other and bring the QP up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bootstrap {
+    // QP info is exchanged over a TCP socket on the connection port, the path
+    // is built from QpConfig and the configured GID index
+    #[default]
+    Tcp,
+    // The connection manager resolves address and route and connects the QP
+    // on the connection port. GID, path MTU and PSNs are taken from the CM,
+    // the device must be the one the server address routes through.
+    RdmaCm,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    // Reliable connection to a single peer, supports RDMA and atomics
+    #[default]
+    Rc,
+    // Unreliable datagrams of up to one MTU to any UD QP sharing the qkey.
+    // Receive buffers get a 40 byte GRH in front of the payload.
+    Ud {
+        qkey: u32,
+    },
+}
+
+// Where the QP takes its receive buffers from
+#[derive(Clone, Default)]
+pub enum RecvQueue {
+    // A receive queue of its own, sized by QpConfig
+    #[default]
+    Dedicated,
+    // A new SRQ on our PD. Other resources attach to it through IbResource::srq.
+    CreateShared(SrqConfig),
+    // The SRQ of another resource, whose device context and PD are used as well
+    Shared(Arc<SharedReceiveQueue>),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SrqConfig {
+    // Receive WRs the SRQ holds, defaults to min(8192, device maximum)
+    pub max_wr: Option<u32>,
+    // SGEs per receive WR, defaults to min(3, device maximum)
+    pub max_sge: Option<u32>,
+    // Raise IBV_EVENT_SRQ_LIMIT_REACHED once fewer WRs than this are left
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SrqAttributes {
+    pub max_wr: u32,
+    pub max_sge: u32,
+    pub limit: Option<u32>,
+}
+
+impl SrqConfig {
+    const DEFAULT_MAX_WR: u32 = 8192;
+    const DEFAULT_MAX_SGE: u32 = 3;
+
+    pub fn resolve(&self, dev_attr: &ibv_device_attr) -> Result<SrqAttributes, RdmaError> {
+        if dev_attr.max_srq <= 0 {
+            return Err(RdmaError::InvalidQpConfig(
+                "the device does not support SRQs".to_owned(),
+            ));
+        }
+
+        let max_srq_wr = dev_attr.max_srq_wr.max(0) as u32;
+        let max_srq_sge = dev_attr.max_srq_sge.max(0) as u32;
+
+        let max_wr = self.max_wr.unwrap_or(Self::DEFAULT_MAX_WR.min(max_srq_wr));
+        let max_sge = self
+            .max_sge
+            .unwrap_or(Self::DEFAULT_MAX_SGE.min(max_srq_sge));
+
+        if max_wr == 0 || max_wr > max_srq_wr {
+            return Err(RdmaError::InvalidQpConfig(format!(
+                "SRQ depth {} is outside 1..={}",
+                max_wr, max_srq_wr
+            )));
+        }
+
+        if max_sge == 0 || max_sge > max_srq_sge {
+            return Err(RdmaError::InvalidQpConfig(format!(
+                "SRQ SGE count {} is outside 1..={}",
+                max_sge, max_srq_sge
+            )));
+        }
+
+        if let Some(limit) = self.limit {
+            if limit >= max_wr {
+                return Err(RdmaError::InvalidQpConfig(format!(
+                    "SRQ limit {} must be below its depth {}",
+                    limit, max_wr
+                )));
+            }
+        }
+
+        Ok(SrqAttributes {
+            max_wr,
+            max_sge,
+            limit: self.limit,
+        })
+    }
+}
+
+// How IbResource::poll_cq_hybrid waits for completions
+#[derive(Debug, Clone, Copy, Default)]
+pub enum CompletionMode {
+    // Busy poll the CQ, lowest latency for a whole core
+    #[default]
+    Polling,
+    // The CQ gets a completion channel. After idle_polls empty polls in a row
+    // the CQ is armed and the channel fd waited on for up to max_wait, which
+    // has millisecond granularity.
+    Hybrid {
+        idle_polls: u32,
+        max_wait: Duration,
+    },
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -18,3 +149,152 @@ pub enum ConnectionType {
         message_size: usize,
     },
 }
+
+// QP attributes used while creating the QP and moving it to RTS. Anything
+// left as None is derived from the port and device attributes in `resolve`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QpConfig {
+    // Path MTU in bytes (256, 512, 1024, 2048 or 4096), defaults to the active MTU of the port
+    pub path_mtu: Option<u32>,
+    pub min_rnr_timer: Option<u8>,
+    pub timeout: Option<u8>,
+    pub retry_cnt: Option<u8>,
+    pub rnr_retry: Option<u8>,
+    // Outstanding RDMA READ / atomic requests as initiator and as target
+    pub max_rd_atomic: Option<u8>,
+    pub max_dest_rd_atomic: Option<u8>,
+    // Defaults to the configured gid_index
+    pub sgid_index: Option<u8>,
+    pub hop_limit: Option<u8>,
+    pub traffic_class: Option<u8>,
+    pub service_level: Option<u8>,
+    pub max_send_wr: Option<u32>,
+    pub max_recv_wr: Option<u32>,
+    pub max_send_sge: Option<u32>,
+    pub max_recv_sge: Option<u32>,
+    // Bytes a send WR may carry inline, the device may grant more. Unset asks
+    // for a default and goes without inline data if the device refuses it.
+    pub max_inline_data: Option<u32>,
+    // Unsignaled send WRs between two signaled ones, bounded by the send queue depth
+    pub signal_interval: Option<u32>,
+}
+
+// QpConfig with every value filled in
+#[derive(Debug, Clone, Copy)]
+pub struct QpAttributes {
+    pub path_mtu: ibv_mtu::Type,
+    pub min_rnr_timer: u8,
+    pub timeout: u8,
+    pub retry_cnt: u8,
+    pub rnr_retry: u8,
+    pub max_rd_atomic: u8,
+    pub max_dest_rd_atomic: u8,
+    pub sgid_index: u8,
+    pub hop_limit: u8,
+    pub traffic_class: u8,
+    pub service_level: u8,
+    pub max_send_wr: u32,
+    pub max_recv_wr: u32,
+    pub max_send_sge: u32,
+    pub max_recv_sge: u32,
+    // None unless the QpConfig set it, see inline_data_request
+    pub max_inline_data: Option<u32>,
+    pub signal_interval: u32,
+}
+
+impl QpAttributes {
+    // Inline bytes to ask the device for when creating the QP
+    pub fn inline_data_request(&self) -> u32 {
+        self.max_inline_data
+            .unwrap_or(QpConfig::DEFAULT_MAX_INLINE_DATA)
+    }
+}
+
+impl QpConfig {
+    const DEFAULT_MAX_WR: u32 = 8192;
+    const DEFAULT_MAX_SGE: u32 = 3;
+    const DEFAULT_SIGNAL_INTERVAL: u32 = 64;
+    const DEFAULT_MAX_INLINE_DATA: u32 = 64;
+
+    pub fn resolve(
+        &self,
+        port_attr: &ibv_port_attr,
+        dev_attr: &ibv_device_attr,
+        gid_index: Option<NonZeroI32>,
+    ) -> Result<QpAttributes, RdmaError> {
+        let path_mtu = match self.path_mtu {
+            Some(bytes) => mtu_from_bytes(bytes).map_err(RdmaError::InvalidQpConfig)?,
+            None => port_attr.active_mtu,
+        };
+
+        let clamp_u8 = |value: i32| value.clamp(0, u8::MAX as i32) as u8;
+        let clamp_u32 = |value: i32| value.max(0) as u32;
+
+        let sgid_index = match self.sgid_index {
+            Some(index) => index,
+            None => match gid_index {
+                Some(index) => u8::try_from(index.get()).map_err(|_| {
+                    RdmaError::InvalidQpConfig(format!(
+                        "gid index {} does not fit a sgid index",
+                        index
+                    ))
+                })?,
+                None => 0,
+            },
+        };
+
+        let max_send_wr = self
+            .max_send_wr
+            .unwrap_or(Self::DEFAULT_MAX_WR.min(clamp_u32(dev_attr.max_qp_wr)));
+
+        // Half the queue at most, so unsignaled WRs can never fill it
+        let signal_interval = self
+            .signal_interval
+            .unwrap_or(Self::DEFAULT_SIGNAL_INTERVAL)
+            .clamp(1, (max_send_wr / 2).max(1));
+
+        Ok(QpAttributes {
+            path_mtu,
+            min_rnr_timer: self.min_rnr_timer.unwrap_or(12),
+            timeout: self.timeout.unwrap_or(14),
+            retry_cnt: self.retry_cnt.unwrap_or(7),
+            rnr_retry: self.rnr_retry.unwrap_or(7),
+            max_rd_atomic: self
+                .max_rd_atomic
+                .unwrap_or(clamp_u8(dev_attr.max_qp_init_rd_atom)),
+            max_dest_rd_atomic: self
+                .max_dest_rd_atomic
+                .unwrap_or(clamp_u8(dev_attr.max_qp_rd_atom)),
+            sgid_index,
+            hop_limit: self.hop_limit.unwrap_or(1),
+            traffic_class: self.traffic_class.unwrap_or(0),
+            service_level: self.service_level.unwrap_or(0),
+            max_send_wr,
+            max_recv_wr: self
+                .max_recv_wr
+                .unwrap_or(Self::DEFAULT_MAX_WR.min(clamp_u32(dev_attr.max_qp_wr))),
+            max_send_sge: self
+                .max_send_sge
+                .unwrap_or(Self::DEFAULT_MAX_SGE.min(clamp_u32(dev_attr.max_sge))),
+            max_recv_sge: self
+                .max_recv_sge
+                .unwrap_or(Self::DEFAULT_MAX_SGE.min(clamp_u32(dev_attr.max_sge))),
+            max_inline_data: self.max_inline_data,
+            signal_interval,
+        })
+    }
+}
+
+pub fn mtu_from_bytes(bytes: u32) -> Result<ibv_mtu::Type, String> {
+    match bytes {
+        256 => Ok(ibv_mtu::IBV_MTU_256),
+        512 => Ok(ibv_mtu::IBV_MTU_512),
+        1024 => Ok(ibv_mtu::IBV_MTU_1024),
+        2048 => Ok(ibv_mtu::IBV_MTU_2048),
+        4096 => Ok(ibv_mtu::IBV_MTU_4096),
+        _ => Err(format!(
+            "invalid path MTU {}, expected 256, 512, 1024, 2048 or 4096",
+            bytes
+        )),
+    }
+}
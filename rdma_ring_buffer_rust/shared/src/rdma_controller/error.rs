@@ -0,0 +1,364 @@
+use std::{error::Error, fmt::Display, io, time::Duration};
+
+use super::{qp_info::QpInfoError, work_completion::WcStatus};
+
+#[derive(Debug)]
+pub enum RdmaError {
+    GetIbDeviceError(io::Error),
+    DeviceNotFound {
+        name: String,
+        available: Vec<String>,
+    },
+    OpenIbDeviceError {
+        name: String,
+        source: io::Error,
+    },
+    AllocPdError(io::Error),
+    QueryPortError {
+        port: u8,
+        source: io::Error,
+    },
+    PortNotActive {
+        port: u8,
+        state: u32,
+    },
+    QueryDeviceError(io::Error),
+    QueryGidError {
+        port: u8,
+        gid_index: i32,
+        source: io::Error,
+    },
+    InvalidQpConfig(String),
+    CreateCompChannelError(io::Error),
+    CreateCqError(io::Error),
+    CreateSrqError(io::Error),
+    ModifySrqError(io::Error),
+    GetAsyncEventError(io::Error),
+    ModifyQpError {
+        from_state: &'static str,
+        to_state: &'static str,
+        source: io::Error,
+    },
+    RegMrError(io::Error),
+    CreateQpError(io::Error),
+    CreateAhError(io::Error),
+    NotUd,
+    DatagramTooLarge {
+        wr_id: u64,
+        len: usize,
+        mtu: usize,
+    },
+    PostSendError {
+        wr_id: u64,
+        source: io::Error,
+    },
+    PostRecvError {
+        wr_id: u64,
+        source: io::Error,
+    },
+    PollCqError(i32),
+    NoCompletionChannel,
+    ReqNotifyCqError(io::Error),
+    GetCqEventError(io::Error),
+    WorkCompletionError {
+        wr_id: u64,
+        status: WcStatus,
+        // ibv_wc_status_str of the status
+        message: &'static str,
+        vendor_err: u32,
+    },
+    BootstrapError(io::Error),
+    CmError {
+        op: &'static str,
+        source: io::Error,
+    },
+    CmUnexpectedEvent {
+        expected: String,
+        received: String,
+        status: i32,
+    },
+    CmDeviceMismatch {
+        expected: String,
+        found: String,
+    },
+    CmRejected {
+        status: i32,
+    },
+    InvalidQpInfo(QpInfoError),
+    RemoteRegionOutOfBounds {
+        name: String,
+        offset: u64,
+        len: u64,
+        region_len: u64,
+    },
+    AtomicsUnsupported,
+    SendQueueFull {
+        wr_id: u64,
+        outstanding: usize,
+        capacity: usize,
+    },
+    TooManySges {
+        wr_id: u64,
+        requested: usize,
+        max: u32,
+    },
+    InlineTooLarge {
+        wr_id: u64,
+        len: usize,
+        max: u32,
+    },
+    InlineNotSupported {
+        wr_id: u64,
+        opcode: u32,
+    },
+    MisalignedAtomic {
+        name: String,
+        addr: u64,
+    },
+    FlushTimeout(Duration),
+}
+
+impl RdmaError {
+    // The errno behind the failure, if the failing call reported one
+    pub fn errno(&self) -> Option<i32> {
+        match self.source() {
+            Some(source) => source.downcast_ref::<io::Error>()?.raw_os_error(),
+            None => None,
+        }
+    }
+}
+
+impl Error for RdmaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RdmaError::GetIbDeviceError(source)
+            | RdmaError::OpenIbDeviceError { source, .. }
+            | RdmaError::AllocPdError(source)
+            | RdmaError::QueryPortError { source, .. }
+            | RdmaError::QueryDeviceError(source)
+            | RdmaError::QueryGidError { source, .. }
+            | RdmaError::CreateCompChannelError(source)
+            | RdmaError::CreateCqError(source)
+            | RdmaError::ReqNotifyCqError(source)
+            | RdmaError::GetCqEventError(source)
+            | RdmaError::CreateSrqError(source)
+            | RdmaError::ModifySrqError(source)
+            | RdmaError::GetAsyncEventError(source)
+            | RdmaError::ModifyQpError { source, .. }
+            | RdmaError::RegMrError(source)
+            | RdmaError::CreateQpError(source)
+            | RdmaError::CreateAhError(source)
+            | RdmaError::PostSendError { source, .. }
+            | RdmaError::PostRecvError { source, .. }
+            | RdmaError::BootstrapError(source)
+            | RdmaError::CmError { source, .. } => Some(source),
+            RdmaError::InvalidQpInfo(source) => Some(source),
+            RdmaError::DeviceNotFound { .. }
+            | RdmaError::PortNotActive { .. }
+            | RdmaError::InvalidQpConfig(_)
+            | RdmaError::PollCqError(_)
+            | RdmaError::NoCompletionChannel
+            | RdmaError::NotUd
+            | RdmaError::DatagramTooLarge { .. }
+            | RdmaError::WorkCompletionError { .. }
+            | RdmaError::CmUnexpectedEvent { .. }
+            | RdmaError::CmDeviceMismatch { .. }
+            | RdmaError::CmRejected { .. }
+            | RdmaError::RemoteRegionOutOfBounds { .. }
+            | RdmaError::AtomicsUnsupported
+            | RdmaError::TooManySges { .. }
+            | RdmaError::SendQueueFull { .. }
+            | RdmaError::InlineTooLarge { .. }
+            | RdmaError::InlineNotSupported { .. }
+            | RdmaError::MisalignedAtomic { .. }
+            | RdmaError::FlushTimeout(_) => None,
+        }
+    }
+}
+
+impl Display for RdmaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RdmaError::GetIbDeviceError(source) => {
+                write!(f, "Failed to get IB device list: {}", source)
+            }
+            RdmaError::DeviceNotFound { name, available } => write!(
+                f,
+                "IB device {} not found, available devices: [{}]",
+                name,
+                available.join(", ")
+            ),
+            RdmaError::OpenIbDeviceError { name, source } => {
+                write!(f, "Failed to open IB device {}: {}", name, source)
+            }
+            RdmaError::AllocPdError(source) => {
+                write!(f, "Failed to allocate protection domain: {}", source)
+            }
+            RdmaError::QueryPortError { port, source } => {
+                write!(f, "Failed to query port {}: {}", port, source)
+            }
+            RdmaError::PortNotActive { port, state } => {
+                write!(f, "Port {} is not active (state {})", port, state)
+            }
+            RdmaError::QueryDeviceError(source) => {
+                write!(f, "Failed to query device: {}", source)
+            }
+            RdmaError::QueryGidError {
+                port,
+                gid_index,
+                source,
+            } => write!(
+                f,
+                "Failed to query gid index {} on port {}: {}",
+                gid_index, port, source
+            ),
+            RdmaError::InvalidQpConfig(msg) => write!(f, "Invalid QP configuration: {}", msg),
+            RdmaError::CreateCompChannelError(source) => {
+                write!(f, "Failed to create completion channel: {}", source)
+            }
+            RdmaError::CreateCqError(source) => {
+                write!(f, "Failed to create completion queue: {}", source)
+            }
+            RdmaError::CreateQpError(source) => {
+                write!(f, "Failed to create queue pair: {}", source)
+            }
+            RdmaError::CreateAhError(source) => {
+                write!(f, "Failed to create address handle: {}", source)
+            }
+            RdmaError::NotUd => write!(f, "The QP does not use the UD transport"),
+            RdmaError::DatagramTooLarge { wr_id, len, mtu } => write!(
+                f,
+                "Datagram {} of {} bytes does not fit the MTU of {}",
+                wr_id, len, mtu
+            ),
+            RdmaError::CreateSrqError(source) => {
+                write!(f, "Failed to create shared receive queue: {}", source)
+            }
+            RdmaError::ModifySrqError(source) => {
+                write!(f, "Failed to modify shared receive queue: {}", source)
+            }
+            RdmaError::GetAsyncEventError(source) => {
+                write!(f, "Failed to get async event: {}", source)
+            }
+            RdmaError::ModifyQpError {
+                from_state,
+                to_state,
+                source,
+            } => write!(
+                f,
+                "Failed to modify QP from {} to {}: {}",
+                from_state, to_state, source
+            ),
+            RdmaError::RegMrError(source) => {
+                write!(f, "Failed to register memory region: {}", source)
+            }
+            RdmaError::PostSendError { wr_id, source } => {
+                write!(f, "Failed to post send wr {}: {}", wr_id, source)
+            }
+            RdmaError::PostRecvError { wr_id, source } => {
+                write!(f, "Failed to post recv wr {}: {}", wr_id, source)
+            }
+            RdmaError::NoCompletionChannel => {
+                write!(f, "The CQ was created without a completion channel")
+            }
+            RdmaError::ReqNotifyCqError(source) => {
+                write!(f, "Failed to arm completion queue: {}", source)
+            }
+            RdmaError::GetCqEventError(source) => {
+                write!(f, "Failed to get completion event: {}", source)
+            }
+            RdmaError::PollCqError(ret) => {
+                write!(
+                    f,
+                    "Failed to poll completion queue with return value {}",
+                    ret
+                )
+            }
+            RdmaError::WorkCompletionError {
+                wr_id,
+                status,
+                message,
+                vendor_err,
+            } => write!(
+                f,
+                "Work request {} failed: {} ({:?}, vendor error {:#x})",
+                wr_id, message, status, vendor_err
+            ),
+            RdmaError::BootstrapError(source) => {
+                write!(f, "Failed to exchange connection info: {}", source)
+            }
+            RdmaError::CmError { op, source } => write!(f, "{} failed: {}", op, source),
+            RdmaError::CmUnexpectedEvent {
+                expected,
+                received,
+                status,
+            } => write!(
+                f,
+                "Expected CM event {}, received {} with status {}",
+                expected, received, status
+            ),
+            RdmaError::CmRejected { status } => {
+                write!(f, "The peer rejected the connection with status {}", status)
+            }
+            RdmaError::CmDeviceMismatch { expected, found } => write!(
+                f,
+                "The CM resolved the peer through {} instead of {}",
+                found, expected
+            ),
+            RdmaError::InvalidQpInfo(source) => {
+                write!(f, "Received invalid connection info: {}", source)
+            }
+            RdmaError::RemoteRegionOutOfBounds {
+                name,
+                offset,
+                len,
+                region_len,
+            } => write!(
+                f,
+                "{} bytes at offset {} are outside remote region {} of {} bytes",
+                len, offset, name, region_len
+            ),
+            RdmaError::AtomicsUnsupported => {
+                write!(f, "The device does not support remote atomics")
+            }
+            RdmaError::SendQueueFull {
+                wr_id,
+                outstanding,
+                capacity,
+            } => write!(
+                f,
+                "Send queue is full posting wr {}: {} of {} slots in use",
+                wr_id, outstanding, capacity
+            ),
+            RdmaError::InlineTooLarge { wr_id, len, max } => write!(
+                f,
+                "Work request {} inlines {} bytes, the QP allows {}",
+                wr_id, len, max
+            ),
+            RdmaError::InlineNotSupported { wr_id, opcode } => write!(
+                f,
+                "Work request {} asks for inline data, opcode {} carries none",
+                wr_id, opcode
+            ),
+            RdmaError::TooManySges {
+                wr_id,
+                requested,
+                max,
+            } => write!(
+                f,
+                "Work request {} has {} SGEs, the QP allows {}",
+                wr_id, requested, max
+            ),
+            RdmaError::MisalignedAtomic { name, addr } => write!(
+                f,
+                "Atomic target {:#x} in remote region {} is not 8 byte aligned",
+                addr, name
+            ),
+            RdmaError::FlushTimeout(timeout) => write!(
+                f,
+                "Flushed work requests did not complete within {:?}",
+                timeout
+            ),
+        }
+    }
+}
@@ -3,6 +3,8 @@ use std::ops::DerefMut;
 
 use std::ops::Deref;
 
+use std::sync::Arc;
+
 use std::mem::MaybeUninit;
 
 use bytemuck::NoUninit;
@@ -10,10 +12,12 @@ use rdma_sys::{ibv_access_flags, ibv_mr, ibv_reg_mr};
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
 
-use super::IbResource;
+use super::{protection_domain::ProtectionDomain, IbResource, RdmaError};
 
 pub struct MemoryRegion {
     pub(crate) mr: *mut ibv_mr,
+    // Keeps the PD allocated until the MR has been deregistered
+    _domain: Arc<ProtectionDomain>,
 }
 
 unsafe impl Send for MemoryRegion {}
@@ -46,7 +50,14 @@ impl IbResource {
     pub fn register_memory_region<'a, T: Sized + FromBytes + AsBytes>(
         &mut self,
         buffer: &'a mut [T],
-    ) -> io::Result<MemoryRegion> {
+    ) -> Result<MemoryRegion, RdmaError> {
+        let domain = self.domain.clone().ok_or_else(|| {
+            RdmaError::RegMrError(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "protection domain is not allocated",
+            ))
+        })?;
+
         unsafe {
             let buffer = buffer.as_bytes();
 
@@ -56,15 +67,19 @@ impl IbResource {
                 buffer.len(),
                 (ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
                     | ibv_access_flags::IBV_ACCESS_REMOTE_WRITE
-                    | ibv_access_flags::IBV_ACCESS_REMOTE_READ)
+                    | ibv_access_flags::IBV_ACCESS_REMOTE_READ
+                    | ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC)
                     .0 as i32,
             );
 
             if mr.is_null() {
-                return Err(io::Error::last_os_error());
+                return Err(RdmaError::RegMrError(io::Error::last_os_error()));
             }
 
-            Ok(MemoryRegion { mr })
+            Ok(MemoryRegion {
+                mr,
+                _domain: domain,
+            })
         }
     }
 }
@@ -0,0 +1,33 @@
+use rdma_sys::{ibv_close_device, ibv_context, ibv_dealloc_pd, ibv_pd};
+
+use super::errno;
+
+// Owns the PD and the device context it was allocated from. Shared between
+// the IbResource and every MemoryRegion, so the PD is only deallocated (and
+// the device closed) once the last registration is gone.
+pub(crate) struct ProtectionDomain {
+    pub(crate) ctx: *mut ibv_context,
+    pub(crate) pd: *mut ibv_pd,
+}
+
+unsafe impl Send for ProtectionDomain {}
+
+unsafe impl Sync for ProtectionDomain {}
+
+impl Drop for ProtectionDomain {
+    fn drop(&mut self) {
+        unsafe {
+            let ret = ibv_dealloc_pd(self.pd);
+
+            if ret != 0 {
+                eprintln!("Failed to deallocate protection domain: {}", errno(ret));
+            }
+
+            let ret = ibv_close_device(self.ctx);
+
+            if ret != 0 {
+                eprintln!("Failed to close IB device: {}", errno(ret));
+            }
+        }
+    }
+}
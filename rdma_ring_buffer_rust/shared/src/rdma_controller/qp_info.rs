@@ -1,6 +1,13 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+};
+
 use derivative::Derivative;
 use rdma_sys::ibv_gid;
 
+use super::RdmaError;
+
 #[derive(Derivative)]
 #[derivative(Debug, Clone, Copy)]
 pub struct DestQpInfo {
@@ -10,3 +17,280 @@ pub struct DestQpInfo {
     #[derivative(Debug = "ignore")]
     pub gid: ibv_gid,
 }
+
+// Optional record appended after the fixed fields. Peers skip kinds they do
+// not know, so new ones can be added without bumping the version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub kind: u16,
+    pub value: Vec<u8>,
+}
+
+// Connection exchange on the wire, every integer is big-endian:
+//
+//   magic   u32  "RDQP"
+//   version u16
+//   flags   u16  reserved, sent as 0
+//   length  u32  bytes that follow, fixed fields and extensions
+//   lid     u16
+//   pad     u16
+//   qpn     u32
+//   psn     u32
+//   gid     [u8; 16]
+//   extensions, each: kind u16, len u16, value [u8; len]
+pub const QP_INFO_MAGIC: u32 = u32::from_be_bytes(*b"RDQP");
+pub const QP_INFO_VERSION: u16 = 1;
+
+// Extension kinds
+pub const EXTENSION_REMOTE_REGION: u16 = 1;
+// u64 stream position the sender reconnects at, see IbResource::set_resume_position
+pub const EXTENSION_RESUME_POSITION: u16 = 2;
+
+const HEADER_LEN: usize = 12;
+const FIXED_LEN: usize = 28;
+const EXTENSION_HEADER_LEN: usize = 4;
+const MAX_LEN: u32 = 64 << 10;
+
+// Bounds of a whole message, header included
+pub(super) const MIN_MESSAGE_LEN: usize = HEADER_LEN + FIXED_LEN;
+pub(super) const MAX_MESSAGE_LEN: usize = HEADER_LEN + MAX_LEN as usize;
+
+// QPNs and PSNs are 24 bit values
+const MAX_QPN: u32 = (1 << 24) - 1;
+const MAX_PSN: u32 = (1 << 24) - 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QpInfoError {
+    BadMagic(u32),
+    UnsupportedVersion(u16),
+    BadLength(u32),
+    InvalidQpn(u32),
+    InvalidPsn(u32),
+    // Neither a LID nor a GID to address the peer with
+    NoAddress,
+    TruncatedExtension { offset: usize },
+    ExtensionTooLong(usize),
+    InvalidRemoteRegion(String),
+    InvalidResumePosition(usize),
+}
+
+impl std::error::Error for QpInfoError {}
+
+impl Display for QpInfoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QpInfoError::BadMagic(magic) => write!(f, "bad magic {:#010x}", magic),
+            QpInfoError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}", version)
+            }
+            QpInfoError::BadLength(len) => write!(f, "bad message length {}", len),
+            QpInfoError::InvalidQpn(qpn) => write!(f, "invalid QP number {:#x}", qpn),
+            QpInfoError::InvalidPsn(psn) => write!(f, "invalid PSN {:#x}", psn),
+            QpInfoError::NoAddress => write!(f, "neither LID nor GID is set"),
+            QpInfoError::TruncatedExtension { offset } => {
+                write!(f, "extension at offset {} is truncated", offset)
+            }
+            QpInfoError::ExtensionTooLong(len) => {
+                write!(f, "extension of {} bytes is too long", len)
+            }
+            QpInfoError::InvalidRemoteRegion(msg) => write!(f, "invalid remote region: {}", msg),
+            QpInfoError::InvalidResumePosition(len) => {
+                write!(f, "resume position of {} bytes, expected 8", len)
+            }
+        }
+    }
+}
+
+impl DestQpInfo {
+    pub fn new(lid: u16, qpn: u32, gid: ibv_gid) -> Self {
+        DestQpInfo {
+            lid,
+            qpn,
+            psn: rand::random::<u32>() & MAX_PSN,
+            gid,
+        }
+    }
+
+    pub fn encode(&self, extensions: &[Extension]) -> Result<Vec<u8>, QpInfoError> {
+        let mut body = Vec::with_capacity(FIXED_LEN);
+
+        body.extend_from_slice(&self.lid.to_be_bytes());
+        body.extend_from_slice(&0u16.to_be_bytes());
+        body.extend_from_slice(&self.qpn.to_be_bytes());
+        body.extend_from_slice(&self.psn.to_be_bytes());
+        body.extend_from_slice(unsafe { &self.gid.raw });
+
+        for extension in extensions {
+            let len = u16::try_from(extension.value.len())
+                .map_err(|_| QpInfoError::ExtensionTooLong(extension.value.len()))?;
+
+            body.extend_from_slice(&extension.kind.to_be_bytes());
+            body.extend_from_slice(&len.to_be_bytes());
+            body.extend_from_slice(&extension.value);
+        }
+
+        let len = body.len() as u32;
+
+        if len > MAX_LEN {
+            return Err(QpInfoError::BadLength(len));
+        }
+
+        let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+
+        message.extend_from_slice(&QP_INFO_MAGIC.to_be_bytes());
+        message.extend_from_slice(&QP_INFO_VERSION.to_be_bytes());
+        message.extend_from_slice(&0u16.to_be_bytes());
+        message.extend_from_slice(&len.to_be_bytes());
+        message.extend_from_slice(&body);
+
+        Ok(message)
+    }
+
+    pub fn decode(message: &[u8]) -> Result<(Self, Vec<Extension>), QpInfoError> {
+        let header: &[u8; HEADER_LEN] = message
+            .get(..HEADER_LEN)
+            .and_then(|header| header.try_into().ok())
+            .ok_or(QpInfoError::BadLength(message.len() as u32))?;
+
+        let len = decode_header(header)?;
+
+        if message.len() - HEADER_LEN != len {
+            return Err(QpInfoError::BadLength((message.len() - HEADER_LEN) as u32));
+        }
+
+        decode_body(&message[HEADER_LEN..])
+    }
+
+    pub fn write_to(
+        &self,
+        mut writer: impl Write,
+        extensions: &[Extension],
+    ) -> Result<(), RdmaError> {
+        let message = self.encode(extensions).map_err(RdmaError::InvalidQpInfo)?;
+
+        writer
+            .write_all(&message)
+            .map_err(RdmaError::BootstrapError)
+    }
+
+    // Reads one message, the length is checked before the body is read
+    pub fn read_from(mut reader: impl Read) -> Result<(Self, Vec<Extension>), RdmaError> {
+        let mut header = [0u8; HEADER_LEN];
+
+        reader
+            .read_exact(&mut header)
+            .map_err(RdmaError::BootstrapError)?;
+
+        let len = decode_header(&header).map_err(RdmaError::InvalidQpInfo)?;
+
+        let mut body = vec![0u8; len];
+
+        reader
+            .read_exact(&mut body)
+            .map_err(RdmaError::BootstrapError)?;
+
+        decode_body(&body).map_err(RdmaError::InvalidQpInfo)
+    }
+}
+
+pub fn resume_position_extension(position: u64) -> Extension {
+    Extension {
+        kind: EXTENSION_RESUME_POSITION,
+        value: position.to_be_bytes().to_vec(),
+    }
+}
+
+// The resume position among the extensions, the last one wins
+pub fn resume_position(extensions: &[Extension]) -> Result<Option<u64>, QpInfoError> {
+    let mut position = None;
+
+    for extension in extensions {
+        if extension.kind != EXTENSION_RESUME_POSITION {
+            continue;
+        }
+
+        let value: [u8; 8] = extension
+            .value
+            .as_slice()
+            .try_into()
+            .map_err(|_| QpInfoError::InvalidResumePosition(extension.value.len()))?;
+
+        position = Some(u64::from_be_bytes(value));
+    }
+
+    Ok(position)
+}
+
+fn decode_header(header: &[u8; HEADER_LEN]) -> Result<usize, QpInfoError> {
+    let magic = u32::from_be_bytes(header[0..4].try_into().unwrap());
+
+    if magic != QP_INFO_MAGIC {
+        return Err(QpInfoError::BadMagic(magic));
+    }
+
+    let version = u16::from_be_bytes(header[4..6].try_into().unwrap());
+
+    if version != QP_INFO_VERSION {
+        return Err(QpInfoError::UnsupportedVersion(version));
+    }
+
+    let len = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+    if len < FIXED_LEN as u32 || len > MAX_LEN {
+        return Err(QpInfoError::BadLength(len));
+    }
+
+    Ok(len as usize)
+}
+
+fn decode_body(body: &[u8]) -> Result<(DestQpInfo, Vec<Extension>), QpInfoError> {
+    if body.len() < FIXED_LEN {
+        return Err(QpInfoError::BadLength(body.len() as u32));
+    }
+
+    let lid = u16::from_be_bytes(body[0..2].try_into().unwrap());
+    let qpn = u32::from_be_bytes(body[4..8].try_into().unwrap());
+    let psn = u32::from_be_bytes(body[8..12].try_into().unwrap());
+    let gid = ibv_gid {
+        raw: body[12..28].try_into().unwrap(),
+    };
+
+    if qpn == 0 || qpn > MAX_QPN {
+        return Err(QpInfoError::InvalidQpn(qpn));
+    }
+
+    if psn > MAX_PSN {
+        return Err(QpInfoError::InvalidPsn(psn));
+    }
+
+    if lid == 0 && body[12..28].iter().all(|&byte| byte == 0) {
+        return Err(QpInfoError::NoAddress);
+    }
+
+    let mut extensions = vec![];
+    let mut offset = FIXED_LEN;
+
+    while offset < body.len() {
+        let truncated = QpInfoError::TruncatedExtension { offset };
+
+        let header = body
+            .get(offset..offset + EXTENSION_HEADER_LEN)
+            .ok_or(truncated.clone())?;
+
+        let kind = u16::from_be_bytes(header[0..2].try_into().unwrap());
+        let len = u16::from_be_bytes(header[2..4].try_into().unwrap()) as usize;
+
+        let start = offset + EXTENSION_HEADER_LEN;
+
+        let value = body.get(start..start + len).ok_or(truncated)?;
+
+        extensions.push(Extension {
+            kind,
+            value: value.to_vec(),
+        });
+
+        offset = start + len;
+    }
+
+    Ok((DestQpInfo { lid, qpn, psn, gid }, extensions))
+}
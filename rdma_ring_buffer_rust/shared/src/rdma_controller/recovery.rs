@@ -0,0 +1,277 @@
+use std::{
+    mem::zeroed,
+    net::Shutdown,
+    os::fd::{AsRawFd, BorrowedFd},
+    ptr::null_mut,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use rdma_sys::*;
+
+use super::{
+    config::{ConnectionType, Transport},
+    errno,
+    send::SendTracker,
+    work_completion::WorkCompletion,
+    IbResource, RdmaError, State,
+};
+
+// connection_lost looks at the bootstrap link once every this many calls
+const LIVENESS_INTERVAL: u32 = 1024;
+
+// How long draining a QP in error waits for its flushed WRs
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// wr_ids of the markers posted behind the flushed WRs, see drain_flushed.
+// They never reach the caller, who must not use them for WRs of its own.
+pub const SEND_FLUSH_MARKER: u64 = u64::MAX;
+pub const RECV_FLUSH_MARKER: u64 = u64::MAX - 1;
+
+// A client connecting before the server listens is rejected by rdma_cm or
+// refused by TCP. It tries this many times, pausing in between, the pause
+// doubles from CONNECT_BACKOFF up to CONNECT_MAX_BACKOFF.
+pub(super) const CONNECT_ATTEMPTS: u32 = 20;
+pub(super) const CONNECT_BACKOFF: Duration = Duration::from_millis(10);
+pub(super) const CONNECT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+// Recovering from a failed QP goes in two steps. `flush` moves the QP to the
+// error state and hands back whatever completed successfully before the
+// failure. Once the caller has applied those and told us its resume position,
+// `reconnect` resets the QP and runs the bootstrap again, which also tells the
+// peer's side its position. Memory regions and advertised regions survive.
+impl IbResource {
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    // Whether the QP failed or the peer dropped the connection. A failed
+    // completion is noticed at once. The peer going away is only seen on the
+    // bootstrap link, which is checked every LIVENESS_INTERVAL calls so this
+    // can sit in a polling loop. A side that only receives has no other way
+    // of learning that the sender reset its QP.
+    pub fn connection_lost(&mut self) -> Result<bool, RdmaError> {
+        match self.state {
+            State::Connected => {}
+            State::Error | State::Disconnected => return Ok(true),
+            State::Init => return Ok(false),
+        }
+
+        self.liveness_polls += 1;
+
+        if self.liveness_polls < LIVENESS_INTERVAL {
+            return Ok(false);
+        }
+
+        self.liveness_polls = 0;
+
+        let fd = match (&self.cm, &self.bootstrap_stream) {
+            (Some(cm), _) => cm.event_fd(),
+            (None, Some(stream)) => stream.as_raw_fd(),
+            // UD keeps no link to the peer
+            (None, None) => return Ok(false),
+        };
+
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+
+        // The peer never writes to the link after connecting, anything
+        // readable is an EOF, a reset or a CM event
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+
+        match poll(&mut fds, PollTimeout::ZERO) {
+            Ok(0) | Err(nix::errno::Errno::EINTR) => Ok(false),
+            Ok(_) => {
+                eprintln!("Peer dropped the connection");
+
+                self.state = State::Error;
+
+                Ok(true)
+            }
+            Err(err) => Err(RdmaError::BootstrapError(err.into())),
+        }
+    }
+
+    // Moves the QP to the error state, which flushes every outstanding WR, and
+    // drains the CQ. Returns the completions that succeeded before the failure,
+    // flushed and failed ones are dropped. The bootstrap link is closed, so the
+    // peer notices and recovers as well.
+    pub fn flush(&mut self) -> Result<Vec<WorkCompletion>, RdmaError> {
+        self.modify_qp_state(ibv_qp_state::IBV_QPS_ERR, "ERR")?;
+
+        if let Some(stream) = self.bootstrap_stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        // Disconnects, the peer gets RDMA_CM_EVENT_DISCONNECTED
+        self.cm = None;
+
+        let mut succeeded = vec![];
+
+        self.drain_flushed(|wc| {
+            if wc.is_success() {
+                succeeded.push(*wc);
+            }
+        })?;
+
+        // Nothing is outstanding any more
+        self.send_tracker =
+            SendTracker::new(self.qp_attrs.map_or(1, |attrs| attrs.signal_interval));
+        self.empty_polls = 0;
+        self.liveness_polls = 0;
+        self.ud_peer = None;
+        self.state = State::Disconnected;
+
+        Ok(succeeded)
+    }
+
+    // Resets the QP and connects it again the way `connect` did the first time.
+    // Call `flush` first. Server and client keep their roles, the client
+    // retries for a while until the server listens again.
+    pub fn reconnect(&mut self) -> Result<(), RdmaError> {
+        if self.state != State::Disconnected {
+            self.flush()?;
+        }
+
+        self.peer_resume_position = None;
+
+        let mut attempts = 1;
+        let mut backoff = CONNECT_BACKOFF;
+
+        loop {
+            self.modify_qp_state(ibv_qp_state::IBV_QPS_RESET, "RESET")?;
+
+            match self.connect() {
+                // The TCP bootstrap retries in connect_retry, rdma_cm gets rejected
+                Err(RdmaError::CmRejected { .. })
+                    if attempts < CONNECT_ATTEMPTS
+                        && matches!(self.connection_type, Some(ConnectionType::Client { .. })) =>
+                {
+                    self.cm = None;
+
+                    sleep(backoff);
+
+                    attempts += 1;
+                    backoff = (backoff * 2).min(CONNECT_MAX_BACKOFF);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Sent to the peer on the next connect. Meant as how far this side got in
+    // the peer's stream, so after a reconnect the peer resumes right there
+    // and neither skips nor repeats anything.
+    pub fn set_resume_position(&mut self, position: u64) {
+        self.resume_position = Some(position);
+    }
+
+    // The position the peer sent on the last connect, None if it sent none
+    pub fn peer_resume_position(&self) -> Option<u64> {
+        self.peer_resume_position
+    }
+
+    // Drains the CQ of a QP in the error state. A marker WR is posted behind
+    // everything outstanding on each queue, the drain ends once both came
+    // back and the CQ ran empty. Flushed completions showing up after that
+    // would otherwise fail the QP again after a reconnect. Every other
+    // completion goes to on_completion.
+    pub(super) fn drain_flushed(
+        &mut self,
+        mut on_completion: impl FnMut(&WorkCompletion),
+    ) -> Result<(), RdmaError> {
+        let (mut send_pending, mut recv_pending) = self.post_flush_markers();
+
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+
+        loop {
+            let polled = self.poll_cq_buffered()?;
+
+            for wc in self.polled(polled) {
+                match wc.wr_id() {
+                    SEND_FLUSH_MARKER => send_pending = false,
+                    RECV_FLUSH_MARKER => recv_pending = false,
+                    _ => on_completion(wc),
+                }
+            }
+
+            if polled > 0 {
+                continue;
+            }
+
+            if !send_pending && !recv_pending {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                return Err(RdmaError::FlushTimeout(FLUSH_TIMEOUT));
+            }
+        }
+    }
+
+    // Returns whether the send and the receive marker went out. A UD QP has
+    // nowhere to send to before it bootstrapped, an SRQ is not flushed with
+    // the QP and a receive queue filled to the brim has no room left. Without
+    // its marker a queue is only drained until the CQ runs empty.
+    fn post_flush_markers(&mut self) -> (bool, bool) {
+        let wr = match (self.transport, &self.ud_peer) {
+            (Transport::Rc, _) => Some(unsafe { zeroed() }),
+            (Transport::Ud { .. }, Some(peer)) => Some(peer.wr()),
+            (Transport::Ud { .. }, None) => None,
+        };
+
+        // post_send_wrs keeps a slot of the send queue free for this one
+        let send = wr.is_some_and(|wr| unsafe {
+            let mut marker = ibv_send_wr {
+                wr_id: SEND_FLUSH_MARKER,
+                opcode: ibv_wr_opcode::IBV_WR_SEND,
+                send_flags: ibv_send_flags::IBV_SEND_SIGNALED.0,
+                wr,
+                ..zeroed()
+            };
+
+            ibv_post_send(self.qp, &mut marker, &mut null_mut()) == 0
+        });
+
+        let recv = self.srq.is_none()
+            && unsafe {
+                let mut marker = ibv_recv_wr {
+                    wr_id: RECV_FLUSH_MARKER,
+                    ..zeroed()
+                };
+
+                ibv_post_recv(self.qp, &mut marker, &mut null_mut()) == 0
+            };
+
+        (send, recv)
+    }
+
+    fn modify_qp_state(
+        &mut self,
+        state: ibv_qp_state::Type,
+        to_state: &'static str,
+    ) -> Result<(), RdmaError> {
+        let mut qp_attr = ibv_qp_attr {
+            qp_state: state,
+            ..unsafe { zeroed() }
+        };
+
+        let ret = unsafe {
+            ibv_modify_qp(
+                self.qp,
+                &mut qp_attr,
+                ibv_qp_attr_mask::IBV_QP_STATE.0 as i32,
+            )
+        };
+
+        if ret != 0 {
+            return Err(RdmaError::ModifyQpError {
+                from_state: "ANY",
+                to_state,
+                source: errno(ret),
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::ops::Range;
+
+use super::{
+    memory_region::MemoryRegion,
+    qp_info::{Extension, QpInfoError, EXTENSION_REMOTE_REGION},
+    RdmaError,
+};
+
+// A window of a peer's registered memory that one-sided operations may target.
+// Addresses are only handed out through `addr`, which checks them against the
+// bounds the peer advertised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRegion {
+    pub name: String,
+    pub addr: u64,
+    pub len: u64,
+    pub rkey: u32,
+}
+
+// addr u64, len u64, rkey u32, followed by the name
+const FIXED_LEN: usize = 20;
+
+impl RemoteRegion {
+    // Remote address of `len` bytes at `offset` into the region
+    pub fn addr(&self, offset: u64, len: u64) -> Result<u64, RdmaError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(self.addr + offset),
+            _ => Err(RdmaError::RemoteRegionOutOfBounds {
+                name: self.name.clone(),
+                offset,
+                len,
+                region_len: self.len,
+            }),
+        }
+    }
+
+    // The part of the region covering `range`, relative to its start
+    pub fn subregion(&self, name: &str, range: Range<u64>) -> Result<RemoteRegion, RdmaError> {
+        let len = range.end.checked_sub(range.start).ok_or_else(|| {
+            RdmaError::RemoteRegionOutOfBounds {
+                name: self.name.clone(),
+                offset: range.start,
+                len: 0,
+                region_len: self.len,
+            }
+        })?;
+
+        Ok(RemoteRegion {
+            name: name.to_owned(),
+            addr: self.addr(range.start, len)?,
+            len,
+            rkey: self.rkey,
+        })
+    }
+
+    pub fn to_extension(&self) -> Extension {
+        let mut value = Vec::with_capacity(FIXED_LEN + self.name.len());
+
+        value.extend_from_slice(&self.addr.to_be_bytes());
+        value.extend_from_slice(&self.len.to_be_bytes());
+        value.extend_from_slice(&self.rkey.to_be_bytes());
+        value.extend_from_slice(self.name.as_bytes());
+
+        Extension {
+            kind: EXTENSION_REMOTE_REGION,
+            value,
+        }
+    }
+
+    pub fn from_extension(extension: &Extension) -> Result<Self, QpInfoError> {
+        let value = &extension.value;
+
+        if value.len() < FIXED_LEN {
+            return Err(QpInfoError::InvalidRemoteRegion(format!(
+                "descriptor of {} bytes is too short",
+                value.len()
+            )));
+        }
+
+        let addr = u64::from_be_bytes(value[0..8].try_into().unwrap());
+        let len = u64::from_be_bytes(value[8..16].try_into().unwrap());
+        let rkey = u32::from_be_bytes(value[16..20].try_into().unwrap());
+
+        let name = std::str::from_utf8(&value[FIXED_LEN..])
+            .map_err(|_| QpInfoError::InvalidRemoteRegion("name is not UTF-8".to_owned()))?;
+
+        if name.is_empty() {
+            return Err(QpInfoError::InvalidRemoteRegion("name is empty".to_owned()));
+        }
+
+        if addr.checked_add(len).is_none() {
+            return Err(QpInfoError::InvalidRemoteRegion(format!(
+                "{} wraps the address space",
+                name
+            )));
+        }
+
+        Ok(RemoteRegion {
+            name: name.to_owned(),
+            addr,
+            len,
+            rkey,
+        })
+    }
+
+    // Collects the regions among the extensions of a received QP info
+    pub fn from_extensions(extensions: &[Extension]) -> Result<Vec<Self>, QpInfoError> {
+        let mut regions: Vec<RemoteRegion> = vec![];
+
+        for extension in extensions {
+            if extension.kind != EXTENSION_REMOTE_REGION {
+                continue;
+            }
+
+            let region = Self::from_extension(extension)?;
+
+            if regions.iter().any(|other| other.name == region.name) {
+                return Err(QpInfoError::InvalidRemoteRegion(format!(
+                    "{} is advertised twice",
+                    region.name
+                )));
+            }
+
+            regions.push(region);
+        }
+
+        Ok(regions)
+    }
+}
+
+impl MemoryRegion {
+    // Describes `range` bytes of this MR for a peer to access
+    pub fn remote_region(
+        &self,
+        name: &str,
+        range: Range<usize>,
+    ) -> Result<RemoteRegion, RdmaError> {
+        let whole = RemoteRegion {
+            name: name.to_owned(),
+            addr: self.addr as u64,
+            len: self.length as u64,
+            rkey: self.rkey,
+        };
+
+        whole.subregion(name, range.start as u64..range.end as u64)
+    }
+}
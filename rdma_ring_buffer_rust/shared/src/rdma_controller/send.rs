@@ -1,10 +1,14 @@
 use std::fmt::Debug;
-use std::{io, mem::zeroed};
+use std::mem::zeroed;
+use std::{collections::VecDeque, ptr::null_mut};
 
 use rdma_sys::*;
+use uninit::out_ref::Out;
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{memory_region::MemoryRegion, IbResource};
+use super::{
+    errno, memory_region::MemoryRegion, remote_region::RemoteRegion, IbResource, RdmaError,
+};
 
 impl IbResource {
     // Safety: data must be part of the memory region
@@ -13,47 +17,788 @@ impl IbResource {
         wr_id: u64,
         mr: &mut MemoryRegion,
         data: &[(impl FromBytes + AsBytes)],
-        signal: bool,
-    ) -> io::Result<()> {
-        unsafe {
-            let mut bad_send_wr = zeroed();
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut list = [sge(mr, data.as_bytes())];
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_SEND,
+            None,
+            None,
+            send_flags,
+        )
+    }
 
-            let lkey = mr.mr.as_ref().unwrap().lkey;
+    // Sends the segments as one message, in order. Empty segments are left out
+    // of the SGE list.
+    // Safety: every segment must be part of the memory region it is paired with
+    pub unsafe fn post_send_sg<'a, T: AsBytes + 'a>(
+        &mut self,
+        wr_id: u64,
+        segments: impl IntoIterator<Item = (&'a MemoryRegion, &'a [T])>,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut list = SgeList::collect(
+            wr_id,
+            segments
+                .into_iter()
+                .map(|(mr, data)| sge(mr, data.as_bytes())),
+            self.qp_cap.max_send_sge,
+        )?;
 
-            let u8_ref = data.as_bytes();
+        self.post_single(
+            wr_id,
+            list.as_mut_slice(),
+            ibv_wr_opcode::IBV_WR_SEND,
+            None,
+            None,
+            send_flags,
+        )
+    }
 
-            let mut list = ibv_sge {
-                addr: u8_ref.as_ptr() as u64,
-                length: u8_ref.len().try_into().unwrap(),
-                lkey,
-            };
+    // Like post_send, the receiver gets imm in its recv work completion
+    // Safety: data must be part of the memory region
+    pub unsafe fn post_send_with_imm(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        data: &[(impl FromBytes + AsBytes)],
+        imm: u32,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut list = [sge(mr, data.as_bytes())];
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_SEND_WITH_IMM,
+            Some(imm),
+            None,
+            send_flags,
+        )
+    }
+
+    // Writes data into `remote` at `remote_offset` bytes, the range is checked
+    // against the bounds the peer advertised.
+    // Safety: data must be part of the memory region
+    pub unsafe fn post_write(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        data: &[(impl FromBytes + AsBytes)],
+        remote: &RemoteRegion,
+        remote_offset: u64,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut list = [sge(mr, data.as_bytes())];
+
+        let wr = remote_target(remote, remote_offset, list[0].length)?;
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_RDMA_WRITE,
+            None,
+            Some(wr),
+            send_flags,
+        )
+    }
+
+    // Like post_write, but also consumes a recv on the remote side, whose
+    // work completion carries imm. data may be empty to only notify the peer.
+    // Safety: data must be part of the memory region
+    pub unsafe fn post_write_with_imm(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        data: &[(impl FromBytes + AsBytes)],
+        remote: &RemoteRegion,
+        remote_offset: u64,
+        imm: u32,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut list = [sge(mr, data.as_bytes())];
+
+        let wr = remote_target(remote, remote_offset, list[0].length)?;
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_RDMA_WRITE_WITH_IMM,
+            Some(imm),
+            Some(wr),
+            send_flags,
+        )
+    }
+
+    // Reads from `remote` at `remote_offset` bytes into buffer, the range is
+    // checked against the bounds the peer advertised.
+    // Safety: buffer must be part of the memory region
+    pub unsafe fn post_read<'a, T: FromBytes>(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        buffer: Out<'a, [T]>,
+        remote: &RemoteRegion,
+        remote_offset: u64,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut pointer = buffer.as_bytes_out();
+
+        let mut list = [ibv_sge {
+            addr: pointer.as_mut_ptr() as *mut u8 as u64,
+            length: pointer.len().try_into().unwrap(),
+            lkey: mr.lkey,
+        }];
+
+        let wr = remote_target(remote, remote_offset, list[0].length)?;
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_RDMA_READ,
+            None,
+            Some(wr),
+            send_flags,
+        )
+    }
+
+    // Atomically adds `add` to the 8 byte word at `remote_offset` of `remote`.
+    // Once the completion for wr_id arrives, old_value holds the word as it
+    // was before the add.
+    // Safety: old_value must be part of the memory region
+    pub unsafe fn post_fetch_add(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        old_value: &mut u64,
+        remote: &RemoteRegion,
+        remote_offset: u64,
+        add: u64,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let remote_addr = self.atomic_target(remote, remote_offset)?;
+
+        let mut list = [sge(mr, old_value.as_bytes())];
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_ATOMIC_FETCH_AND_ADD,
+            None,
+            Some(wr_t {
+                atomic: atomic_t {
+                    remote_addr,
+                    compare_add: add,
+                    swap: 0,
+                    rkey: remote.rkey,
+                },
+            }),
+            send_flags,
+        )
+    }
+
+    // Atomically replaces the 8 byte word at `remote_offset` of `remote` with
+    // swap if it equals compare. Once the completion for wr_id arrives,
+    // old_value holds the word as it was, so the swap happened iff it equals
+    // compare.
+    // Safety: old_value must be part of the memory region
+    pub unsafe fn post_compare_swap(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        old_value: &mut u64,
+        remote: &RemoteRegion,
+        remote_offset: u64,
+        compare: u64,
+        swap: u64,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let remote_addr = self.atomic_target(remote, remote_offset)?;
+
+        let mut list = [sge(mr, old_value.as_bytes())];
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_ATOMIC_CMP_AND_SWP,
+            None,
+            Some(wr_t {
+                atomic: atomic_t {
+                    remote_addr,
+                    compare_add: compare,
+                    swap,
+                    rkey: remote.rkey,
+                },
+            }),
+            send_flags,
+        )
+    }
+
+    // Atomics need device support and an 8 byte aligned remote word
+    fn atomic_target(&self, remote: &RemoteRegion, remote_offset: u64) -> Result<u64, RdmaError> {
+        let atomic_cap = unsafe { self.dev_attr.assume_init_ref().atomic_cap };
+
+        if atomic_cap == ibv_atomic_cap::IBV_ATOMIC_NONE {
+            return Err(RdmaError::AtomicsUnsupported);
+        }
+
+        let remote_addr = remote.addr(remote_offset, 8)?;
+
+        if remote_addr % 8 != 0 {
+            return Err(RdmaError::MisalignedAtomic {
+                name: remote.name.clone(),
+                addr: remote_addr,
+            });
+        }
 
-            let send_flags = if signal {
-                ibv_send_flags::IBV_SEND_SIGNALED.0
+        Ok(remote_addr)
+    }
+
+    pub(super) unsafe fn post_single(
+        &mut self,
+        wr_id: u64,
+        list: &mut [ibv_sge],
+        opcode: ibv_wr_opcode::Type,
+        imm: Option<u32>,
+        wr: Option<wr_t>,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mut send_wr = send_wr(wr_id, opcode, imm, wr, send_flags);
+
+        send_wr.sg_list = list.as_mut_ptr();
+        send_wr.num_sge = list.len() as i32;
+
+        self.post_send_wrs(std::slice::from_mut(&mut send_wr))
+    }
+
+    // Posts every WR of the batch with a single ibv_post_send. WRs asked to be
+    // signaled always are, the others only every signal_interval WRs. Payloads
+    // that fit the inline limit are inlined like in every other post.
+    // Safety: every segment in the batch must still be part of its memory region
+    pub unsafe fn post_send_batch(&mut self, batch: &mut SendBatch) -> Result<(), RdmaError> {
+        for (index, wr) in batch.wrs.iter_mut().enumerate() {
+            let sges = &mut batch.sges[batch.sge_starts[index]..batch.sge_ends[index]];
+
+            if sges.len() > self.qp_cap.max_send_sge as usize {
+                return Err(RdmaError::TooManySges {
+                    wr_id: wr.wr_id,
+                    requested: sges.len(),
+                    max: self.qp_cap.max_send_sge,
+                });
+            }
+
+            wr.sg_list = sges.as_mut_ptr();
+            wr.num_sge = sges.len() as i32;
+        }
+
+        self.post_send_wrs(&mut batch.wrs)
+    }
+
+    // Free slots in the send queue, WRs count until a later signaled one completes
+    pub fn send_queue_room(&self) -> usize {
+        self.send_capacity() - self.send_tracker.outstanding
+    }
+
+    // One slot stays free for the marker drain_flushed posts
+    fn send_capacity(&self) -> usize {
+        (self.qp_cap.max_send_wr as usize).saturating_sub(1).max(1)
+    }
+
+    // Payloads up to max_inline_data are copied into the WR when it is posted,
+    // the HCA then neither reads them from memory nor needs their lkey. Only
+    // opcodes that carry outbound data can be inlined, READs and atomics that
+    // ask for it are refused.
+    fn apply_inline(&self, wr: &mut ibv_send_wr) -> Result<(), RdmaError> {
+        let carries_data = matches!(
+            wr.opcode,
+            ibv_wr_opcode::IBV_WR_SEND
+                | ibv_wr_opcode::IBV_WR_SEND_WITH_IMM
+                | ibv_wr_opcode::IBV_WR_RDMA_WRITE
+                | ibv_wr_opcode::IBV_WR_RDMA_WRITE_WITH_IMM
+        );
+
+        let requested = wr.send_flags & ibv_send_flags::IBV_SEND_INLINE.0 != 0;
+
+        if !carries_data {
+            if requested {
+                return Err(RdmaError::InlineNotSupported {
+                    wr_id: wr.wr_id,
+                    opcode: wr.opcode,
+                });
+            }
+
+            return Ok(());
+        }
+
+        let len: usize = if wr.num_sge > 0 {
+            let sges = unsafe { std::slice::from_raw_parts(wr.sg_list, wr.num_sge as usize) };
+
+            sges.iter().map(|sge| sge.length as usize).sum()
+        } else {
+            0
+        };
+
+        let max = self.qp_cap.max_inline_data;
+
+        if len <= max as usize {
+            wr.send_flags |= ibv_send_flags::IBV_SEND_INLINE.0;
+        } else if requested {
+            return Err(RdmaError::InlineTooLarge {
+                wr_id: wr.wr_id,
+                len,
+                max,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Links wrs, applies selective signaling and keeps the send queue accounting
+    unsafe fn post_send_wrs(&mut self, wrs: &mut [ibv_send_wr]) -> Result<(), RdmaError> {
+        if wrs.is_empty() {
+            return Ok(());
+        }
+
+        let capacity = self.send_capacity();
+
+        if self.send_tracker.outstanding + wrs.len() > capacity {
+            return Err(RdmaError::SendQueueFull {
+                wr_id: wrs[0].wr_id,
+                outstanding: self.send_tracker.outstanding,
+                capacity,
+            });
+        }
+
+        let mut tracker = self.send_tracker.clone();
+
+        let wrs_ptr = wrs.as_mut_ptr();
+        let len = wrs.len();
+
+        for wr in wrs.iter_mut() {
+            self.apply_inline(wr)?;
+        }
+
+        for (index, wr) in wrs.iter_mut().enumerate() {
+            let forced = wr.send_flags & ibv_send_flags::IBV_SEND_SIGNALED.0 != 0;
+
+            if tracker.on_post(forced, capacity) {
+                wr.send_flags |= ibv_send_flags::IBV_SEND_SIGNALED.0;
+            }
+
+            wr.next = if index + 1 < len {
+                unsafe { wrs_ptr.add(index + 1) }
             } else {
-                0
+                null_mut()
             };
+        }
+
+        unsafe {
+            let mut bad_send_wr: *mut ibv_send_wr = null_mut();
+
+            let ret = ibv_post_send(self.qp, wrs_ptr, &mut bad_send_wr);
+
+            if ret != 0 {
+                // WRs ahead of the bad one went out and still need accounting
+                let posted = if bad_send_wr.is_null() {
+                    0
+                } else {
+                    bad_send_wr.offset_from(wrs_ptr) as usize
+                };
+
+                self.send_tracker.record_posted(
+                    wrs[..posted]
+                        .iter()
+                        .map(|wr| wr.send_flags & ibv_send_flags::IBV_SEND_SIGNALED.0 != 0),
+                );
 
-            let mut send_wr = ibv_send_wr {
+                return Err(RdmaError::PostSendError {
+                    wr_id: wrs[posted.min(len - 1)].wr_id,
+                    source: errno(ret),
+                });
+            }
+        }
+
+        self.send_tracker = tracker;
+
+        Ok(())
+    }
+
+    // Posts every WR of the batch with a single ibv_post_recv
+    // Safety: every buffer in the batch must still be part of its memory region
+    pub unsafe fn post_recv_batch(&mut self, batch: &mut RecvBatch) -> Result<(), RdmaError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let wrs = batch.link(self.max_recv_sge())?;
+
+        unsafe {
+            let mut bad_recv_wr: *mut ibv_recv_wr = null_mut();
+
+            let ret = self.post_recv_wrs(wrs, &mut bad_recv_wr);
+
+            if ret != 0 {
+                return Err(RdmaError::PostRecvError {
+                    wr_id: batch.failed_wr_id(bad_recv_wr),
+                    source: errno(ret),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Send queue accounting for selective signaling. An unsignaled WR leaves its
+// slot occupied until a later signaled WR completes, so every signaled WR
+// remembers how many slots its completion frees.
+#[derive(Debug, Clone)]
+pub struct SendTracker {
+    signal_interval: usize,
+    // WRs posted whose slots are not free yet
+    outstanding: usize,
+    // Unsignaled WRs since the last signaled one
+    unsignaled: usize,
+    // Slots freed by each signaled WR still in flight, oldest first
+    signaled: VecDeque<usize>,
+}
+
+impl SendTracker {
+    pub fn new(signal_interval: u32) -> Self {
+        SendTracker {
+            signal_interval: signal_interval.max(1) as usize,
+            outstanding: 0,
+            unsignaled: 0,
+            signaled: VecDeque::new(),
+        }
+    }
+
+    // Decides whether the next WR is signaled and records it. The WR that
+    // fills the queue is always signaled so the queue can drain again.
+    pub fn on_post(&mut self, forced: bool, capacity: usize) -> bool {
+        let signaled = forced
+            || self.unsignaled + 1 >= self.signal_interval
+            || self.outstanding + 1 >= capacity;
+
+        self.record(signaled);
+
+        signaled
+    }
+
+    // Accounts for the WRs that went out ahead of a failed one, signaled as
+    // on_post decided on a copy of the tracker
+    pub fn record_posted(&mut self, signaled: impl IntoIterator<Item = bool>) {
+        for signaled in signaled {
+            self.record(signaled);
+        }
+    }
+
+    fn record(&mut self, signaled: bool) {
+        self.outstanding += 1;
+        self.unsignaled += 1;
+
+        if signaled {
+            self.signaled.push_back(self.unsignaled);
+            self.unsignaled = 0;
+        }
+    }
+
+    // Send queue completions arrive in posting order
+    pub fn on_completion(&mut self) {
+        if let Some(freed) = self.signaled.pop_front() {
+            self.outstanding -= freed;
+        }
+    }
+
+    // Send queue slots taken, see IbResource::send_queue_room
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+}
+
+// Send WRs chained into one post by IbResource::post_send_batch. Only the
+// addresses of the data are kept, nothing is checked until the batch is posted.
+#[derive(Default)]
+pub struct SendBatch {
+    wrs: Vec<ibv_send_wr>,
+    sges: Vec<ibv_sge>,
+    sge_starts: Vec<usize>,
+    sge_ends: Vec<usize>,
+}
+
+impl SendBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.wrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wrs.is_empty()
+    }
+
+    // Empties the batch, keeping its allocations for reuse
+    pub fn clear(&mut self) {
+        self.wrs.clear();
+        self.sges.clear();
+        self.sge_starts.clear();
+        self.sge_ends.clear();
+    }
+
+    // A send of the segments as one message, empty segments are left out
+    pub fn send<'a, T: AsBytes + 'a>(
+        &mut self,
+        wr_id: u64,
+        segments: impl IntoIterator<Item = (&'a MemoryRegion, &'a [T])>,
+        send_flags: u32,
+    ) -> &mut Self {
+        let sges = segments
+            .into_iter()
+            .map(|(mr, data)| sge(mr, data.as_bytes()))
+            .filter(|sge| sge.length > 0);
+
+        self.push(
+            send_wr(wr_id, ibv_wr_opcode::IBV_WR_SEND, None, None, send_flags),
+            sges,
+        )
+    }
+
+    // An RDMA WRITE of data into `remote` at `remote_offset` bytes
+    pub fn write(
+        &mut self,
+        wr_id: u64,
+        mr: &MemoryRegion,
+        data: &[(impl FromBytes + AsBytes)],
+        remote: &RemoteRegion,
+        remote_offset: u64,
+        send_flags: u32,
+    ) -> Result<&mut Self, RdmaError> {
+        let list = sge(mr, data.as_bytes());
+
+        let wr = remote_target(remote, remote_offset, list.length)?;
+
+        Ok(self.push(
+            send_wr(
                 wr_id,
-                sg_list: &mut list,
-                num_sge: 1,
-                opcode: ibv_wr_opcode::IBV_WR_SEND,
+                ibv_wr_opcode::IBV_WR_RDMA_WRITE,
+                None,
+                Some(wr),
                 send_flags,
-                ..zeroed()
+            ),
+            [list],
+        ))
+    }
+
+    fn push(&mut self, wr: ibv_send_wr, sges: impl IntoIterator<Item = ibv_sge>) -> &mut Self {
+        self.sge_starts.push(self.sges.len());
+        self.sges.extend(sges);
+        self.sge_ends.push(self.sges.len());
+        self.wrs.push(wr);
+        self
+    }
+}
+
+// Recv WRs chained into one post by IbResource::post_recv_batch
+#[derive(Default)]
+pub struct RecvBatch {
+    wrs: Vec<ibv_recv_wr>,
+    sges: Vec<ibv_sge>,
+    sge_starts: Vec<usize>,
+    sge_ends: Vec<usize>,
+}
+
+impl RecvBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.wrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wrs.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.wrs.clear();
+        self.sges.clear();
+        self.sge_starts.clear();
+        self.sge_ends.clear();
+    }
+
+    // Chains the WRs and points them at their SGEs, returns the first WR
+    pub(super) fn link(&mut self, max_sge: u32) -> Result<*mut ibv_recv_wr, RdmaError> {
+        let wrs_ptr = self.wrs.as_mut_ptr();
+        let len = self.wrs.len();
+
+        for (index, wr) in self.wrs.iter_mut().enumerate() {
+            let sges = &mut self.sges[self.sge_starts[index]..self.sge_ends[index]];
+
+            if sges.len() > max_sge as usize {
+                return Err(RdmaError::TooManySges {
+                    wr_id: wr.wr_id,
+                    requested: sges.len(),
+                    max: max_sge,
+                });
+            }
+
+            wr.sg_list = sges.as_mut_ptr();
+            wr.num_sge = sges.len() as i32;
+            wr.next = if index + 1 < len {
+                unsafe { wrs_ptr.add(index + 1) }
+            } else {
+                null_mut()
             };
+        }
 
-            let errno = ibv_post_send(self.qp, &mut send_wr, &mut bad_send_wr);
+        Ok(wrs_ptr)
+    }
+
+    // wr_id of the WR a failed post stopped at
+    pub(super) fn failed_wr_id(&self, bad_recv_wr: *mut ibv_recv_wr) -> u64 {
+        let failed = if bad_recv_wr.is_null() {
+            0
+        } else {
+            unsafe { bad_recv_wr.offset_from(self.wrs.as_ptr()) as usize }
+        };
+
+        self.wrs[failed.min(self.wrs.len() - 1)].wr_id
+    }
 
-            if errno != 0 {
-                return Err(io::Error::last_os_error());
+    // A recv scattered over the buffers in order, empty buffers are left out
+    pub fn recv<'a, T: FromBytes + 'a>(
+        &mut self,
+        wr_id: u64,
+        segments: impl IntoIterator<Item = (&'a MemoryRegion, Out<'a, [T]>)>,
+    ) -> &mut Self {
+        self.sge_starts.push(self.sges.len());
+
+        for (mr, buffer) in segments {
+            let mut pointer = buffer.as_bytes_out();
+
+            if pointer.len() > 0 {
+                self.sges.push(ibv_sge {
+                    addr: pointer.as_mut_ptr() as *mut u8 as u64,
+                    length: pointer.len().try_into().unwrap(),
+                    lkey: mr.lkey,
+                });
             }
+        }
 
-            return Ok(());
+        self.sge_ends.push(self.sges.len());
+        self.wrs.push(ibv_recv_wr {
+            wr_id,
+            ..unsafe { zeroed() }
+        });
+        self
+    }
+}
+
+fn send_wr(
+    wr_id: u64,
+    opcode: ibv_wr_opcode::Type,
+    imm: Option<u32>,
+    wr: Option<wr_t>,
+    send_flags: u32,
+) -> ibv_send_wr {
+    let mut send_wr = ibv_send_wr {
+        wr_id,
+        opcode,
+        send_flags,
+        ..unsafe { zeroed() }
+    };
+
+    // The immediate travels in network byte order
+    if let Some(imm) = imm {
+        send_wr.imm_data_invalidated_rkey_union.imm_data = imm.to_be();
+    }
+
+    if let Some(wr) = wr {
+        send_wr.wr = wr;
+    }
+
+    send_wr
+}
+
+// Most SGEs a single post gathers, devices rarely allow more per WR
+const MAX_SGE: usize = 32;
+
+// The SGE list of a single post, kept on the stack
+pub(super) struct SgeList {
+    sges: [ibv_sge; MAX_SGE],
+    len: usize,
+}
+
+impl SgeList {
+    // Empty SGEs are left out. Counting goes on past max so the error tells
+    // how many were asked for.
+    pub(super) fn collect(
+        wr_id: u64,
+        sges: impl IntoIterator<Item = ibv_sge>,
+        max: u32,
+    ) -> Result<Self, RdmaError> {
+        let max = (max as usize).min(MAX_SGE);
+
+        let mut list = SgeList {
+            sges: [ibv_sge {
+                addr: 0,
+                length: 0,
+                lkey: 0,
+            }; MAX_SGE],
+            len: 0,
+        };
+
+        for sge in sges.into_iter().filter(|sge| sge.length > 0) {
+            if list.len < max {
+                list.sges[list.len] = sge;
+            }
+
+            list.len += 1;
         }
+
+        if list.len > max {
+            return Err(RdmaError::TooManySges {
+                wr_id,
+                requested: list.len,
+                max: max as u32,
+            });
+        }
+
+        Ok(list)
     }
+
+    pub(super) fn as_mut_slice(&mut self) -> &mut [ibv_sge] {
+        &mut self.sges[..self.len]
+    }
+}
+
+pub(super) fn sge(mr: &MemoryRegion, data: &[u8]) -> ibv_sge {
+    ibv_sge {
+        addr: data.as_ptr() as u64,
+        length: data.len().try_into().unwrap(),
+        lkey: mr.lkey,
+    }
+}
+
+fn remote_target(remote: &RemoteRegion, offset: u64, len: u32) -> Result<wr_t, RdmaError> {
+    Ok(wr_t {
+        rdma: rdma_t {
+            remote_addr: remote.addr(offset, len as u64)?,
+            rkey: remote.rkey,
+        },
+    })
 }
 
+// Flags for the post methods, e.g. SendFlagBuilder::new().signaled().build().
+// Inlining is added on its own for payloads that fit, asking for it makes a
+// payload over the limit an error instead of a regular post.
+#[derive(Debug, Default, Clone, Copy)]
 pub struct SendFlagBuilder {
     flags: u32,
 }
@@ -0,0 +1,183 @@
+use std::{io, ptr::null_mut, sync::Arc};
+
+use rdma_sys::*;
+
+use super::{
+    config::SrqAttributes, errno, protection_domain::ProtectionDomain, send::RecvBatch, IbResource,
+    RdmaError,
+};
+
+// A receive queue shared by every QP attached to it. Recvs consumed through
+// any of the QPs complete on that QP's CQ, so wr_ids should tell the buffers
+// apart rather than the connections. The SRQ keeps its PD alive, QPs on
+// other resources attach to it by sharing that PD and its device context.
+pub struct SharedReceiveQueue {
+    srq: *mut ibv_srq,
+    attrs: SrqAttributes,
+    pub(super) domain: Arc<ProtectionDomain>,
+}
+
+// ibv_post_srq_recv and ibv_modify_srq may be called from any thread
+unsafe impl Send for SharedReceiveQueue {}
+
+unsafe impl Sync for SharedReceiveQueue {}
+
+impl SharedReceiveQueue {
+    pub(super) fn create(
+        domain: &Arc<ProtectionDomain>,
+        attrs: SrqAttributes,
+    ) -> Result<Self, RdmaError> {
+        let mut srq_init_attr = ibv_srq_init_attr {
+            srq_context: null_mut(),
+            attr: ibv_srq_attr {
+                max_wr: attrs.max_wr,
+                max_sge: attrs.max_sge,
+                srq_limit: 0,
+            },
+        };
+
+        let srq = unsafe { ibv_create_srq(domain.pd, &mut srq_init_attr) };
+
+        if srq.is_null() {
+            return Err(RdmaError::CreateSrqError(io::Error::last_os_error()));
+        }
+
+        // The device may round the depth up
+        let srq = SharedReceiveQueue {
+            srq,
+            attrs: SrqAttributes {
+                max_wr: srq_init_attr.attr.max_wr,
+                max_sge: srq_init_attr.attr.max_sge,
+                limit: attrs.limit,
+            },
+            domain: domain.clone(),
+        };
+
+        srq.arm_limit()?;
+
+        Ok(srq)
+    }
+
+    // Identifies the SRQ in AsyncEvent::SrqLimitReached and SrqError
+    pub fn handle(&self) -> u32 {
+        unsafe { (*self.srq).handle }
+    }
+
+    pub fn max_wr(&self) -> u32 {
+        self.attrs.max_wr
+    }
+
+    pub fn max_sge(&self) -> u32 {
+        self.attrs.max_sge
+    }
+
+    // Arms the limit event. It fires once, when fewer than the configured
+    // limit of WRs are left, and has to be armed again after every refill.
+    pub fn arm_limit(&self) -> Result<(), RdmaError> {
+        let Some(limit) = self.attrs.limit else {
+            return Ok(());
+        };
+
+        let mut srq_attr = ibv_srq_attr {
+            max_wr: 0,
+            max_sge: 0,
+            srq_limit: limit,
+        };
+
+        let ret = unsafe {
+            ibv_modify_srq(
+                self.srq,
+                &mut srq_attr,
+                ibv_srq_attr_mask::IBV_SRQ_LIMIT.0 as i32,
+            )
+        };
+
+        if ret != 0 {
+            return Err(RdmaError::ModifySrqError(errno(ret)));
+        }
+
+        Ok(())
+    }
+
+    // Posts every recv of the batch to the SRQ with a single ibv_post_srq_recv
+    // Safety: every buffer in the batch must still be part of its memory region
+    pub unsafe fn post_recv_batch(&self, batch: &mut RecvBatch) -> Result<(), RdmaError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let wrs = batch.link(self.attrs.max_sge)?;
+
+        unsafe {
+            let mut bad_recv_wr: *mut ibv_recv_wr = null_mut();
+
+            let ret = self.post_recv_wrs(wrs, &mut bad_recv_wr);
+
+            if ret != 0 {
+                return Err(RdmaError::PostRecvError {
+                    wr_id: batch.failed_wr_id(bad_recv_wr),
+                    source: errno(ret),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Tops the SRQ up after the limit event and arms the event again
+    // Safety: every buffer in the batch must still be part of its memory region
+    pub unsafe fn refill(&self, batch: &mut RecvBatch) -> Result<(), RdmaError> {
+        self.post_recv_batch(batch)?;
+
+        self.arm_limit()
+    }
+
+    unsafe fn post_recv_wrs(
+        &self,
+        wrs: *mut ibv_recv_wr,
+        bad_recv_wr: *mut *mut ibv_recv_wr,
+    ) -> i32 {
+        ibv_post_srq_recv(self.srq, wrs, bad_recv_wr)
+    }
+
+    pub(super) fn as_ptr(&self) -> *mut ibv_srq {
+        self.srq
+    }
+}
+
+impl Drop for SharedReceiveQueue {
+    fn drop(&mut self) {
+        let ret = unsafe { ibv_destroy_srq(self.srq) };
+
+        if ret != 0 {
+            eprintln!("Failed to destroy shared receive queue: {}", errno(ret));
+        }
+    }
+}
+
+impl IbResource {
+    // The SRQ the QP takes its receive buffers from, clone it into the
+    // RecvQueue::Shared of another resource's Config to share it
+    pub fn srq(&self) -> Option<&Arc<SharedReceiveQueue>> {
+        self.srq.as_ref()
+    }
+
+    pub(super) fn max_recv_sge(&self) -> u32 {
+        match &self.srq {
+            Some(srq) => srq.max_sge(),
+            None => self.qp_cap.max_recv_sge,
+        }
+    }
+
+    // Recvs go to the SRQ once the QP is attached to one
+    pub(super) unsafe fn post_recv_wrs(
+        &self,
+        wrs: *mut ibv_recv_wr,
+        bad_recv_wr: *mut *mut ibv_recv_wr,
+    ) -> i32 {
+        match &self.srq {
+            Some(srq) => srq.post_recv_wrs(wrs, bad_recv_wr),
+            None => ibv_post_recv(self.qp, wrs, bad_recv_wr),
+        }
+    }
+}
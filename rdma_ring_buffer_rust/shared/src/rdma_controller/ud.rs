@@ -0,0 +1,230 @@
+use std::{io, sync::Arc};
+
+use rdma_sys::*;
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{
+    config::Transport, errno, memory_region::MemoryRegion, protection_domain::ProtectionDomain,
+    qp_info::DestQpInfo, send::sge, IbResource, RdmaError,
+};
+
+// Every UD receive starts with room for the GRH, filled in only when the
+// completion has IBV_WC_GRH set. Post the first SGE of a UD recv over it.
+pub const GRH_LEN: usize = 40;
+
+// Route to a peer's port, used by UD sends. Keeps the PD it was created on alive.
+pub struct AddressHandle {
+    ah: *mut ibv_ah,
+    _domain: Arc<ProtectionDomain>,
+}
+
+unsafe impl Send for AddressHandle {}
+
+unsafe impl Sync for AddressHandle {}
+
+impl Drop for AddressHandle {
+    fn drop(&mut self) {
+        let ret = unsafe { ibv_destroy_ah(self.ah) };
+
+        if ret != 0 {
+            eprintln!("Failed to destroy address handle: {}", errno(ret));
+        }
+    }
+}
+
+// Everything a UD send needs to reach a remote QP
+pub struct UdDestination {
+    pub ah: Arc<AddressHandle>,
+    pub qpn: u32,
+    pub qkey: u32,
+}
+
+impl UdDestination {
+    // The addressing part of a send WR to this destination
+    pub(super) fn wr(&self) -> wr_t {
+        wr_t {
+            ud: ud_t {
+                ah: self.ah.ah,
+                remote_qpn: self.qpn,
+                remote_qkey: self.qkey,
+            },
+        }
+    }
+}
+
+// Global route header as it lands in the first GRH_LEN bytes of a UD receive.
+// On RoCE v2 over IPv4 the last 20 bytes hold the IPv4 header instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grh {
+    pub version_tclass_flow: u32,
+    pub payload_len: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub sgid: [u8; 16],
+    pub dgid: [u8; 16],
+}
+
+impl Grh {
+    pub fn parse(bytes: &[u8]) -> Option<Grh> {
+        let bytes: &[u8; GRH_LEN] = bytes.get(..GRH_LEN)?.try_into().ok()?;
+
+        Some(Grh {
+            version_tclass_flow: u32::from_be_bytes(bytes[0..4].try_into().unwrap()),
+            payload_len: u16::from_be_bytes(bytes[4..6].try_into().unwrap()),
+            next_header: bytes[6],
+            hop_limit: bytes[7],
+            sgid: bytes[8..24].try_into().unwrap(),
+            dgid: bytes[24..40].try_into().unwrap(),
+        })
+    }
+
+    pub fn traffic_class(&self) -> u8 {
+        (self.version_tclass_flow >> 20) as u8
+    }
+
+    pub fn flow_label(&self) -> u32 {
+        self.version_tclass_flow & 0xf_ffff
+    }
+}
+
+impl IbResource {
+    pub fn create_ah(&self, dest: &DestQpInfo) -> Result<AddressHandle, RdmaError> {
+        let (Some(domain), Some(attrs)) = (&self.domain, &self.qp_attrs) else {
+            return Err(RdmaError::CreateAhError(io::Error::from(
+                io::ErrorKind::NotConnected,
+            )));
+        };
+
+        let mut ah_attr = self.ah_attr(dest, attrs);
+
+        let ah = unsafe { ibv_create_ah(domain.pd, &mut ah_attr) };
+
+        if ah.is_null() {
+            return Err(RdmaError::CreateAhError(io::Error::last_os_error()));
+        }
+
+        Ok(AddressHandle {
+            ah,
+            _domain: domain.clone(),
+        })
+    }
+
+    // Address of the UD QP described by dest, using our qkey
+    pub fn ud_destination(&self, dest: &DestQpInfo) -> Result<UdDestination, RdmaError> {
+        let Transport::Ud { qkey } = self.transport else {
+            return Err(RdmaError::NotUd);
+        };
+
+        Ok(UdDestination {
+            ah: Arc::new(self.create_ah(dest)?),
+            qpn: dest.qpn,
+            qkey,
+        })
+    }
+
+    // The peer a UD QP exchanged QP info with in `connect`
+    pub fn ud_peer(&self) -> Option<&UdDestination> {
+        self.ud_peer.as_ref()
+    }
+
+    // UD QPs have no peer in their state, RESET -> INIT sets the qkey and
+    // RTR and RTS only move the state along. Nothing is exchanged, so this is
+    // all a QP used for discovery needs before its first send.
+    pub fn set_qp_ud_ready(&mut self) -> Result<(), RdmaError> {
+        let Transport::Ud { qkey } = self.transport else {
+            return Err(RdmaError::NotUd);
+        };
+
+        let transitions = [
+            (
+                "RESET",
+                ibv_qp_attr {
+                    qp_state: ibv_qp_state::IBV_QPS_RESET,
+                    ..unsafe { std::mem::zeroed() }
+                },
+                ibv_qp_attr_mask::IBV_QP_STATE,
+            ),
+            (
+                "INIT",
+                ibv_qp_attr {
+                    qp_state: ibv_qp_state::IBV_QPS_INIT,
+                    pkey_index: 0,
+                    port_num: self.port_num,
+                    qkey,
+                    ..unsafe { std::mem::zeroed() }
+                },
+                ibv_qp_attr_mask::IBV_QP_STATE
+                    | ibv_qp_attr_mask::IBV_QP_PKEY_INDEX
+                    | ibv_qp_attr_mask::IBV_QP_PORT
+                    | ibv_qp_attr_mask::IBV_QP_QKEY,
+            ),
+            (
+                "RTR",
+                ibv_qp_attr {
+                    qp_state: ibv_qp_state::IBV_QPS_RTR,
+                    ..unsafe { std::mem::zeroed() }
+                },
+                ibv_qp_attr_mask::IBV_QP_STATE,
+            ),
+            (
+                "RTS",
+                ibv_qp_attr {
+                    qp_state: ibv_qp_state::IBV_QPS_RTS,
+                    sq_psn: self.local_psn,
+                    ..unsafe { std::mem::zeroed() }
+                },
+                ibv_qp_attr_mask::IBV_QP_STATE | ibv_qp_attr_mask::IBV_QP_SQ_PSN,
+            ),
+        ];
+
+        let mut from_state = "ANY";
+
+        for (to_state, mut qp_attr, mask) in transitions {
+            let ret = unsafe { ibv_modify_qp(self.qp, &mut qp_attr, mask.0 as i32) };
+
+            if ret != 0 {
+                return Err(RdmaError::ModifyQpError {
+                    from_state,
+                    to_state,
+                    source: errno(ret),
+                });
+            }
+
+            from_state = to_state;
+        }
+
+        Ok(())
+    }
+
+    // Sends data as one datagram, which must fit the active MTU of the port
+    // Safety: data must be part of the memory region
+    pub unsafe fn post_send_ud(
+        &mut self,
+        wr_id: u64,
+        mr: &mut MemoryRegion,
+        data: &[(impl FromBytes + AsBytes)],
+        dest: &UdDestination,
+        send_flags: u32,
+    ) -> Result<(), RdmaError> {
+        let mtu = 128usize << self.port_attr.assume_init_ref().active_mtu;
+
+        if data.as_bytes().len() > mtu {
+            return Err(RdmaError::DatagramTooLarge {
+                wr_id,
+                len: data.as_bytes().len(),
+                mtu,
+            });
+        }
+
+        let mut list = [sge(mr, data.as_bytes())];
+
+        self.post_single(
+            wr_id,
+            &mut list,
+            ibv_wr_opcode::IBV_WR_SEND,
+            None,
+            Some(dest.wr()),
+            send_flags,
+        )
+    }
+}
@@ -1,23 +1,206 @@
 use std::{
+    ffi::CStr,
     fmt::Debug,
     ops::{Deref, DerefMut},
 };
 
-use rdma_sys::ibv_wc;
+use rdma_sys::{ibv_wc, ibv_wc_flags, ibv_wc_opcode, ibv_wc_status, ibv_wc_status_str};
 
+use super::RdmaError;
+
+// Transparent, so a buffer of them can be handed to ibv_poll_cq
+#[repr(transparent)]
+#[derive(Clone, Copy)]
 pub struct WorkCompletion(ibv_wc);
 
 impl Debug for WorkCompletion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("WorkCompletion")
             .field("wr_id", &self.0.wr_id)
-            .field("status", &self.0.status)
-            .field("opcode", &self.0.opcode)
+            .field("status", &self.status())
+            .field("opcode", &self.opcode())
             .field("byte_len", &self.0.byte_len)
+            .field("imm_data", &self.imm_data())
             .finish()
     }
 }
 
+// ibv_wc_status. Anything newer than this list comes out as Other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WcStatus {
+    Success,
+    LocLenErr,
+    LocQpOpErr,
+    LocEecOpErr,
+    LocProtErr,
+    // The QP went to the error state before the WR was executed
+    WrFlushErr,
+    MwBindErr,
+    BadRespErr,
+    LocAccessErr,
+    RemInvReqErr,
+    RemAccessErr,
+    RemOpErr,
+    RetryExcErr,
+    RnrRetryExcErr,
+    LocRddViolErr,
+    RemInvRdReqErr,
+    RemAbortErr,
+    InvEecnErr,
+    InvEecStateErr,
+    FatalErr,
+    RespTimeoutErr,
+    GeneralErr,
+    Other(u32),
+}
+
+impl From<ibv_wc_status::Type> for WcStatus {
+    fn from(status: ibv_wc_status::Type) -> Self {
+        match status {
+            ibv_wc_status::IBV_WC_SUCCESS => WcStatus::Success,
+            ibv_wc_status::IBV_WC_LOC_LEN_ERR => WcStatus::LocLenErr,
+            ibv_wc_status::IBV_WC_LOC_QP_OP_ERR => WcStatus::LocQpOpErr,
+            ibv_wc_status::IBV_WC_LOC_EEC_OP_ERR => WcStatus::LocEecOpErr,
+            ibv_wc_status::IBV_WC_LOC_PROT_ERR => WcStatus::LocProtErr,
+            ibv_wc_status::IBV_WC_WR_FLUSH_ERR => WcStatus::WrFlushErr,
+            ibv_wc_status::IBV_WC_MW_BIND_ERR => WcStatus::MwBindErr,
+            ibv_wc_status::IBV_WC_BAD_RESP_ERR => WcStatus::BadRespErr,
+            ibv_wc_status::IBV_WC_LOC_ACCESS_ERR => WcStatus::LocAccessErr,
+            ibv_wc_status::IBV_WC_REM_INV_REQ_ERR => WcStatus::RemInvReqErr,
+            ibv_wc_status::IBV_WC_REM_ACCESS_ERR => WcStatus::RemAccessErr,
+            ibv_wc_status::IBV_WC_REM_OP_ERR => WcStatus::RemOpErr,
+            ibv_wc_status::IBV_WC_RETRY_EXC_ERR => WcStatus::RetryExcErr,
+            ibv_wc_status::IBV_WC_RNR_RETRY_EXC_ERR => WcStatus::RnrRetryExcErr,
+            ibv_wc_status::IBV_WC_LOC_RDD_VIOL_ERR => WcStatus::LocRddViolErr,
+            ibv_wc_status::IBV_WC_REM_INV_RD_REQ_ERR => WcStatus::RemInvRdReqErr,
+            ibv_wc_status::IBV_WC_REM_ABORT_ERR => WcStatus::RemAbortErr,
+            ibv_wc_status::IBV_WC_INV_EECN_ERR => WcStatus::InvEecnErr,
+            ibv_wc_status::IBV_WC_INV_EEC_STATE_ERR => WcStatus::InvEecStateErr,
+            ibv_wc_status::IBV_WC_FATAL_ERR => WcStatus::FatalErr,
+            ibv_wc_status::IBV_WC_RESP_TIMEOUT_ERR => WcStatus::RespTimeoutErr,
+            ibv_wc_status::IBV_WC_GENERAL_ERR => WcStatus::GeneralErr,
+            other => WcStatus::Other(other),
+        }
+    }
+}
+
+// ibv_wc_opcode. Receive side opcodes have IBV_WC_RECV set, see is_recv.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WcOpcode {
+    Send,
+    RdmaWrite,
+    RdmaRead,
+    CompSwap,
+    FetchAdd,
+    BindMw,
+    LocalInv,
+    Recv,
+    RecvRdmaWithImm,
+    Other(u32),
+}
+
+impl WcOpcode {
+    pub fn is_recv(&self) -> bool {
+        match self {
+            WcOpcode::Recv | WcOpcode::RecvRdmaWithImm => true,
+            WcOpcode::Other(opcode) => opcode & ibv_wc_opcode::IBV_WC_RECV != 0,
+            _ => false,
+        }
+    }
+}
+
+impl From<ibv_wc_opcode::Type> for WcOpcode {
+    fn from(opcode: ibv_wc_opcode::Type) -> Self {
+        match opcode {
+            ibv_wc_opcode::IBV_WC_SEND => WcOpcode::Send,
+            ibv_wc_opcode::IBV_WC_RDMA_WRITE => WcOpcode::RdmaWrite,
+            ibv_wc_opcode::IBV_WC_RDMA_READ => WcOpcode::RdmaRead,
+            ibv_wc_opcode::IBV_WC_COMP_SWAP => WcOpcode::CompSwap,
+            ibv_wc_opcode::IBV_WC_FETCH_ADD => WcOpcode::FetchAdd,
+            ibv_wc_opcode::IBV_WC_BIND_MW => WcOpcode::BindMw,
+            ibv_wc_opcode::IBV_WC_LOCAL_INV => WcOpcode::LocalInv,
+            ibv_wc_opcode::IBV_WC_RECV => WcOpcode::Recv,
+            ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM => WcOpcode::RecvRdmaWithImm,
+            other => WcOpcode::Other(other),
+        }
+    }
+}
+
+impl WorkCompletion {
+    pub fn wr_id(&self) -> u64 {
+        self.0.wr_id
+    }
+
+    pub fn status(&self) -> WcStatus {
+        self.0.status.into()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.0.status == ibv_wc_status::IBV_WC_SUCCESS
+    }
+
+    // Only valid for successful completions
+    pub fn opcode(&self) -> WcOpcode {
+        self.0.opcode.into()
+    }
+
+    // Bytes received, or transferred by an RDMA READ or atomic
+    pub fn byte_len(&self) -> u32 {
+        self.0.byte_len
+    }
+
+    // QP the datagram came from, only set for UD receives
+    pub fn src_qp(&self) -> u32 {
+        self.0.src_qp
+    }
+
+    pub fn flags(&self) -> ibv_wc_flags {
+        ibv_wc_flags(self.0.wc_flags)
+    }
+
+    pub fn vendor_err(&self) -> u32 {
+        self.0.vendor_err
+    }
+
+    // The completion itself if it succeeded, otherwise an error naming the
+    // status the way libibverbs does, along with the vendor error code
+    pub fn into_result(self) -> Result<Self, RdmaError> {
+        if self.is_success() {
+            return Ok(self);
+        }
+
+        // ibv_wc_status_str returns static strings, "unknown" for unknown codes
+        let message = unsafe { CStr::from_ptr(ibv_wc_status_str(self.0.status)) }
+            .to_str()
+            .unwrap_or("unknown");
+
+        Err(RdmaError::WorkCompletionError {
+            wr_id: self.0.wr_id,
+            status: self.status(),
+            message,
+            vendor_err: self.0.vendor_err,
+        })
+    }
+
+    // Whether the first GRH_LEN bytes of a UD receive hold the sender's GRH
+    pub fn has_grh(&self) -> bool {
+        self.flags().0 & ibv_wc_flags::IBV_WC_GRH.0 != 0
+    }
+
+    // Immediate value of a SEND_WITH_IMM or RDMA_WRITE_WITH_IMM, in host byte order
+    pub fn imm_data(&self) -> Option<u32> {
+        if self.flags().0 & ibv_wc_flags::IBV_WC_WITH_IMM.0 == 0 {
+            return None;
+        }
+
+        unsafe {
+            Some(u32::from_be(
+                self.0.imm_data_invalidated_rkey_union.imm_data,
+            ))
+        }
+    }
+}
+
 impl From<ibv_wc> for WorkCompletion {
     fn from(wc: ibv_wc) -> Self {
         WorkCompletion(wc)
@@ -18,13 +18,24 @@ pub mod sender;
 pub mod writer_chunk;
 
 // Safety: The Ref must not outlive the underlying RingBuffer
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct RefRingBuffer<T> {
     head: *const AtomicUsize,
     tail: *const AtomicUsize,
     buffer: *mut [MaybeUninit<T>],
 }
 
+// Only the pointers are copied, so no `T: Clone` bound is needed
+impl<T> Clone for RefRingBuffer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            head: self.head,
+            tail: self.tail,
+            buffer: self.buffer,
+        }
+    }
+}
+
 unsafe impl<T: Send> Send for RefRingBuffer<T> {}
 unsafe impl<T: Send> Sync for RefRingBuffer<T> {}
 
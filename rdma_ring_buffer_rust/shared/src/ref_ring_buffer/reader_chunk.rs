@@ -14,7 +14,7 @@ impl<T: Copy + Send + Debug> Debug for ReadChunk<'_, T> {
         f.debug_struct("RingBufferReader")
             .field("start", &self.start)
             .field("end", &self.end)
-            .field("buffer", &self.deref())
+            .field("buffer", &self.as_slices())
             .finish()
     }
 }
@@ -25,12 +25,63 @@ impl<T: Send + Copy> ReadChunk<'_, T> {
             .head_ref()
             .store(self.end, std::sync::atomic::Ordering::Release);
     }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Shrinks the chunk to its first len elements
+    pub fn truncate(&mut self, len: usize) {
+        self.end = self.start + len.min(self.len());
+    }
+
+    // Whether the chunk crosses the end of the buffer, it then only comes
+    // apart through as_slices and slices_at
+    pub fn wraps(&self) -> bool {
+        let start = self.start % self.ring_buffer.buffer_size();
+
+        start + self.len() > self.ring_buffer.buffer_size()
+    }
+
+    // The part up to the end of the buffer and the wrapped part, which is empty
+    // unless the chunk crosses the end
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        self.slices_at(0, self.len())
+    }
+
+    // Like as_slices for the len elements at offset into the chunk
+    pub fn slices_at(&self, offset: usize, len: usize) -> (&[T], &[T]) {
+        assert!(offset + len <= self.len());
+
+        let buffer_size = self.ring_buffer.buffer_size();
+        let start = (self.start + offset) % buffer_size;
+        let length = len;
+
+        let first = length.min(buffer_size - start);
+
+        unsafe {
+            let buffer = transmute::<&[std::mem::MaybeUninit<T>], &[T]>(
+                self.ring_buffer.buffer.as_ref().unwrap(),
+            );
+
+            (&buffer[start..start + first], &buffer[..length - first])
+        }
+    }
 }
 
 impl<T: Send + Copy> Deref for ReadChunk<'_, T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
+        assert!(
+            !self.wraps(),
+            "ReadChunk wraps around the end of the ring buffer, use as_slices"
+        );
+
         let start = self.start % self.ring_buffer.buffer_size();
         let length = self.end - self.start;
 
@@ -1,18 +1,32 @@
+use std::marker::PhantomData;
+
 use crate::atomic_extension::AtomicExtension;
 
 use super::{reader_chunk::ReadChunk, RefRingBuffer};
 
 pub struct Receiver<'a, T> {
-    ring_buffer: &'a RefRingBuffer<T>
+    ring_buffer: RefRingBuffer<T>,
+    _marker: PhantomData<&'a RefRingBuffer<T>>,
 }
 
 impl<'a, T> Receiver<'a, T> {
     pub(super) fn new(ring_buffer: &'a RefRingBuffer<T>) -> Self {
-        Self { ring_buffer }
+        Self {
+            ring_buffer: ring_buffer.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    // Safety: the memory behind the ring buffer must stay mapped for 'a
+    pub(crate) unsafe fn from_owned(ring_buffer: RefRingBuffer<T>) -> Self {
+        Self {
+            ring_buffer,
+            _marker: PhantomData,
+        }
     }
 
     pub fn ring_buffer(&self) -> &RefRingBuffer<T> {
-        self.ring_buffer
+        &self.ring_buffer
     }
 }
 
@@ -34,13 +48,46 @@ impl<'a, T: Copy + Send> Receiver<'a, T> {
             }
 
             Some(ReadChunk {
-                ring_buffer: self.ring_buffer,
+                ring_buffer: &self.ring_buffer,
                 start: head,
                 end: head + len,
             })
         }
     }
 
+    // Unlike read_exact the chunk may wrap around the end of the buffer, use
+    // ReadChunk::as_slices to get at both parts
+    pub fn read_exact_wrapping(&self, len: usize) -> Option<ReadChunk<T>> {
+        unsafe {
+            let head = self.ring_buffer.head_ref().load_acquire();
+            let tail = self.ring_buffer.tail_ref().load_acquire();
+
+            if tail - head < len {
+                return None;
+            }
+
+            Some(ReadChunk {
+                ring_buffer: &self.ring_buffer,
+                start: head,
+                end: head + len,
+            })
+        }
+    }
+
+    // Everything readable up to max_len, the chunk may wrap around the end of the buffer
+    pub fn read_wrapping(&self, max_len: usize) -> ReadChunk<T> {
+        unsafe {
+            let head = self.ring_buffer.head_ref().load_acquire();
+            let tail = self.ring_buffer.tail_ref().load_acquire();
+
+            ReadChunk {
+                ring_buffer: &self.ring_buffer,
+                start: head,
+                end: head + (tail - head).min(max_len),
+            }
+        }
+    }
+
     // The reader will only return continuous memory slice regardless of the buffer is wrapped around
     // This ensure that RingBufferReader can be converted into slice
     pub fn read(&self) -> ReadChunk<T> {
@@ -54,7 +101,7 @@ impl<'a, T: Copy + Send> Receiver<'a, T> {
             avaliable = avaliable.min(buffer_size - (head % buffer_size));
             // SAFETY: acquire load for tail will ensure that the data is written before this line
             ReadChunk {
-                ring_buffer: self.ring_buffer,
+                ring_buffer: &self.ring_buffer,
                 start: head,
                 end: head + avaliable,
             }
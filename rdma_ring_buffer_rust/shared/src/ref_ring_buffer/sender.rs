@@ -1,26 +1,48 @@
-use std::{mem::MaybeUninit, ptr};
+use std::{marker::PhantomData, mem::MaybeUninit, ptr};
 
 use crate::atomic_extension::AtomicExtension;
 
 use super::{writer_chunk::WriteChunk, RefRingBuffer};
 
 pub struct Sender<'a, T> {
-    ring_buffer: &'a RefRingBuffer<T>,
+    ring_buffer: RefRingBuffer<T>,
+    _marker: PhantomData<&'a RefRingBuffer<T>>,
 }
 
 impl<'a, T> Sender<'a, T> {
     pub(super) fn new(ring_buffer: &'a RefRingBuffer<T>) -> Self {
-        Self { ring_buffer }
+        Self {
+            ring_buffer: ring_buffer.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    // Safety: the memory behind the ring buffer must stay mapped for 'a
+    pub(crate) unsafe fn from_owned(ring_buffer: RefRingBuffer<T>) -> Self {
+        Self {
+            ring_buffer,
+            _marker: PhantomData,
+        }
     }
 
     pub fn ring_buffer(&self) -> &RefRingBuffer<T> {
-        self.ring_buffer
+        &self.ring_buffer
     }
 }
 
 impl<'a, T: Copy + Send> Sender<'a, T> {
-    pub fn try_reserve(&self, size: usize) -> Option<WriteChunk<'a, T>> {
-        WriteChunk::try_reserve(self.ring_buffer, size)
+    pub fn try_reserve(&self, size: usize) -> Option<WriteChunk<'_, T>> {
+        WriteChunk::try_reserve(&self.ring_buffer, size)
+    }
+
+    // The chunk may wrap around the end of the buffer, see WriteChunk::as_mut_slices
+    pub fn try_reserve_wrapping(&self, size: usize) -> Option<WriteChunk<'_, T>> {
+        WriteChunk::try_reserve_wrapping(&self.ring_buffer, size)
+    }
+
+    // All free space up to max_size, the chunk may wrap around the end of the buffer
+    pub fn reserve_wrapping(&self, max_size: usize) -> WriteChunk<'_, T> {
+        WriteChunk::reserve_wrapping(&self.ring_buffer, max_size)
     }
 
     // The writer doesn't ensure that the data written is continuous
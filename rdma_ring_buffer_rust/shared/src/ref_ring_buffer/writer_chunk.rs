@@ -42,12 +42,107 @@ impl<'a, T: Copy + Send> WriteChunk<'a, T> {
             })
         }
     }
+
+    pub(super) fn try_reserve_wrapping(
+        ring_buffer: &'a RefRingBuffer<T>,
+        size: usize,
+    ) -> Option<Self> {
+        unsafe {
+            let head = ring_buffer.head_ref().load_acquire();
+            let tail = ring_buffer.tail_ref().load_acquire();
+
+            if ring_buffer.buffer_size() - (tail - head) < size {
+                return None;
+            }
+
+            Some(Self {
+                ring_buffer,
+                start: tail,
+                end: tail + size,
+                _marker: PhantomData,
+            })
+        }
+    }
+
+    pub(super) fn reserve_wrapping(ring_buffer: &'a RefRingBuffer<T>, max_size: usize) -> Self {
+        unsafe {
+            let head = ring_buffer.head_ref().load_acquire();
+            let tail = ring_buffer.tail_ref().load_acquire();
+
+            let free = ring_buffer.buffer_size() - (tail - head);
+
+            Self {
+                ring_buffer,
+                start: tail,
+                end: tail + free.min(max_size),
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Shrinks the chunk to its first len elements
+    pub fn truncate(&mut self, len: usize) {
+        self.end = self.start + len.min(self.len());
+    }
+
+    // Whether the chunk crosses the end of the buffer, it then only comes
+    // apart through as_mut_slices and slices_at
+    pub fn wraps(&self) -> bool {
+        let start = self.start % self.ring_buffer.buffer_size();
+
+        start + self.len() > self.ring_buffer.buffer_size()
+    }
+
+    // The part up to the end of the buffer and the wrapped part, which is empty
+    // unless the chunk crosses the end
+    pub fn as_mut_slices(&mut self) -> (&mut [MaybeUninit<T>], &mut [MaybeUninit<T>]) {
+        self.slices_at(0, self.len())
+    }
+
+    // Like as_mut_slices for the len elements at offset into the chunk
+    pub fn slices_at(
+        &mut self,
+        offset: usize,
+        len: usize,
+    ) -> (&mut [MaybeUninit<T>], &mut [MaybeUninit<T>]) {
+        assert!(offset + len <= self.len());
+
+        unsafe {
+            let buffer_size = self.ring_buffer.buffer_size();
+            let start = (self.start + offset) % buffer_size;
+            let length = len;
+
+            let first = length.min(buffer_size - start);
+
+            let (wrapped, rest) = self
+                .ring_buffer
+                .buffer
+                .as_mut()
+                .unwrap()
+                .split_at_mut(start);
+
+            (&mut rest[..first], &mut wrapped[..length - first])
+        }
+    }
 }
 
 impl<T: Copy + Send> Deref for WriteChunk<'_, T> {
     type Target = [MaybeUninit<T>];
 
     fn deref(&self) -> &Self::Target {
+        assert!(
+            !self.wraps(),
+            "WriteChunk wraps around the end of the ring buffer, use as_mut_slices"
+        );
+
         unsafe {
             let start = self.start % self.ring_buffer.buffer_size();
             let length = self.end - self.start;
@@ -58,6 +153,11 @@ impl<T: Copy + Send> Deref for WriteChunk<'_, T> {
 
 impl<T: Copy + Send> DerefMut for WriteChunk<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        assert!(
+            !self.wraps(),
+            "WriteChunk wraps around the end of the ring buffer, use as_mut_slices"
+        );
+
         unsafe {
             let start = self.start % self.ring_buffer.buffer_size();
             let length = self.end - self.start;
@@ -67,6 +167,19 @@ impl<T: Copy + Send> DerefMut for WriteChunk<'_, T> {
 }
 
 impl<T> WriteChunk<'_, T> {
+    // Publishes the first len elements, the rest stays reserved
+    pub fn commit_prefix(&mut self, len: usize) {
+        assert!(len <= self.end - self.start);
+
+        unsafe {
+            (*self.ring_buffer)
+                .tail
+                .as_ref()
+                .unwrap()
+                .store(self.start + len, std::sync::atomic::Ordering::Release);
+        }
+    }
+
     pub fn commit(&mut self) {
         unsafe {
             (*self.ring_buffer)
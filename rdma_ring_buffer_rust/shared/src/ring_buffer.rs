@@ -30,6 +30,28 @@ impl<T: Send + Copy, const N: usize> RingBufferConst<T, N> {
     }
 }
 
+// The indices of a ring whose buffer lives elsewhere, e.g. right after this
+// header in shared memory with a length only known at runtime
+#[repr(C, align(4096))]
+pub struct RingBufferHeader {
+    pub head: CachePadded<AtomicUsize>,
+    pub tail: CachePadded<AtomicUsize>,
+}
+
+impl RingBufferHeader {
+    pub fn new() -> Self {
+        Self {
+            head: AtomicUsize::new(0).into(),
+            tail: AtomicUsize::new(0).into(),
+        }
+    }
+
+    // Safety: buffer must stay valid for as long as the returned ref is used
+    pub unsafe fn to_ref<T: Send + Copy>(&self, buffer: *mut [MaybeUninit<T>]) -> RefRingBuffer<T> {
+        RefRingBuffer::from_raw_parts(&self.head, &self.tail, buffer)
+    }
+}
+
 #[repr(C)]
 pub struct RingBufferAlloc<T> {
     pub head: CachePadded<AtomicUsize>,
@@ -0,0 +1,135 @@
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        os::{
+            linux::net::SocketAddrExt,
+            unix::net::{SocketAddr, UnixListener},
+        },
+        process, thread,
+        time::Duration,
+    };
+
+    use shared::{
+        client,
+        ipc::{
+            config::{IpcConfig, IpcEndpoint, PeerRule},
+            element_type::{ElementType, RingElement},
+            Ipc,
+        },
+    };
+
+    fn abstract_config(name: &str, allowed_peers: Vec<PeerRule>) -> IpcConfig {
+        IpcConfig {
+            endpoint: IpcEndpoint::Abstract(format!("{name}-{}", process::id())),
+            connect_timeout: Duration::from_secs(5),
+            allowed_peers,
+        }
+    }
+
+    #[test]
+    pub fn ipc_endpoint_parse() {
+        assert_eq!(
+            "@adapter".parse::<IpcEndpoint>().unwrap(),
+            IpcEndpoint::Abstract("adapter".to_owned())
+        );
+        assert_eq!(
+            "/run/adapter.sock".parse::<IpcEndpoint>().unwrap(),
+            IpcEndpoint::Path("/run/adapter.sock".into())
+        );
+
+        assert_eq!("uid:1000".parse::<PeerRule>().unwrap(), PeerRule::Uid(1000));
+        assert_eq!("pid:42".parse::<PeerRule>().unwrap(), PeerRule::Pid(42));
+        assert!("user:1000".parse::<PeerRule>().is_err());
+        assert!("gid:abc".parse::<PeerRule>().is_err());
+    }
+
+    #[test]
+    pub fn element_type_parse() {
+        assert_eq!("bytes".parse::<ElementType>().unwrap(), ElementType::BYTES);
+        assert_eq!("u64".parse::<ElementType>().unwrap(), u64::ELEMENT_TYPE);
+        assert_eq!(
+            "record:8:8:u64".parse::<ElementType>().unwrap(),
+            u64::ELEMENT_TYPE
+        );
+
+        let record = "record:32:8:trade".parse::<ElementType>().unwrap();
+        assert_eq!(record, ElementType::record::<[u64; 4]>("trade"));
+        assert_ne!(record, ElementType::record::<[u64; 4]>("quote"));
+
+        assert!("record:12:8:trade".parse::<ElementType>().is_err());
+        assert!("record:32:3:trade".parse::<ElementType>().is_err());
+        assert!("record:24:8:trade".parse::<ElementType>().is_err());
+        assert!("record:512:8:trade".parse::<ElementType>().is_err());
+        assert!("record:8:4:trade".parse::<ElementType>().is_err());
+        assert!("record:4:4:trade".parse::<ElementType>().is_ok());
+        assert!("f64".parse::<ElementType>().is_err());
+    }
+
+    #[test]
+    pub fn ipc_accepts_allowed_peer() {
+        let config = abstract_config("ipc-accept", vec![PeerRule::Pid(process::id() as i32)]);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut ipc = Ipc::create(&config).unwrap();
+                ipc.write_all(&[7]).unwrap();
+            });
+
+            let mut ipc = Ipc::open(&config).unwrap();
+            let mut buf = [0u8];
+            ipc.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[0], 7);
+        });
+    }
+
+    #[test]
+    pub fn ipc_refuses_unknown_peer() {
+        let config = abstract_config("ipc-refuse", vec![PeerRule::Pid(-1)]);
+        let open_config = abstract_config("ipc-refuse", vec![]);
+
+        // The listener keeps waiting for an allowed peer, so it is left behind
+        thread::spawn(move || Ipc::create(&config));
+
+        let err = Ipc::open(&open_config).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    pub fn ipc_open_times_out() {
+        let mut config = abstract_config("ipc-timeout", vec![]);
+        config.connect_timeout = Duration::from_millis(20);
+
+        let err = Ipc::open(&config).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    pub fn ipc_open_times_out_without_reply() {
+        let mut config = abstract_config("ipc-silent", vec![]);
+        config.connect_timeout = Duration::from_millis(50);
+
+        // Accepts the connection but never answers it
+        let IpcEndpoint::Abstract(name) = &config.endpoint else {
+            unreachable!()
+        };
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixListener::bind_addr(&addr).unwrap();
+
+        let err = Ipc::open(&config).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        drop(listener);
+    }
+
+    #[test]
+    pub fn attach_as_checks_the_layout() {
+        let config = abstract_config("ipc-layout", vec![]);
+        let record = "record:32:8:trade".parse::<ElementType>().unwrap();
+
+        let err = client::attach_as::<[u64; 2]>(&config, record)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
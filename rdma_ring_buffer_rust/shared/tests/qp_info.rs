@@ -0,0 +1,149 @@
+#[cfg(test)]
+pub mod tests {
+    use rdma_sys::ibv_gid;
+    use shared::rdma_controller::{
+        qp_info::{
+            resume_position, resume_position_extension, DestQpInfo, Extension, QpInfoError,
+            EXTENSION_RESUME_POSITION,
+        },
+        remote_region::RemoteRegion,
+        RdmaError,
+    };
+
+    fn qp_info() -> DestQpInfo {
+        let mut raw = [0u8; 16];
+        raw[15] = 1;
+
+        DestQpInfo::new(7, 0x1234, ibv_gid { raw })
+    }
+
+    #[test]
+    pub fn qp_info_roundtrip() {
+        let info = qp_info();
+        let extensions = vec![Extension {
+            kind: 42,
+            value: b"ring-data".to_vec(),
+        }];
+
+        let message = info.encode(&extensions).unwrap();
+
+        // Fixed layout, independent of the host struct
+        assert_eq!(&message[0..4], b"RDQP");
+        assert_eq!(&message[12..14], &7u16.to_be_bytes());
+
+        let (decoded, decoded_extensions) = DestQpInfo::decode(&message).unwrap();
+
+        assert_eq!(decoded.lid, info.lid);
+        assert_eq!(decoded.qpn, info.qpn);
+        assert_eq!(decoded.psn, info.psn);
+        assert_eq!(unsafe { decoded.gid.raw }, unsafe { info.gid.raw });
+        assert_eq!(decoded_extensions, extensions);
+
+        assert!(info.psn < 1 << 24);
+    }
+
+    #[test]
+    pub fn qp_info_rejects_invalid() {
+        let message = qp_info().encode(&[]).unwrap();
+
+        let mut bad_magic = message.clone();
+        bad_magic[0] = 0;
+        assert!(matches!(
+            DestQpInfo::decode(&bad_magic),
+            Err(QpInfoError::BadMagic(_))
+        ));
+
+        let mut bad_version = message.clone();
+        bad_version[5] = 9;
+        assert_eq!(
+            DestQpInfo::decode(&bad_version).err(),
+            Some(QpInfoError::UnsupportedVersion(9))
+        );
+
+        assert!(matches!(
+            DestQpInfo::decode(&message[..message.len() - 1]),
+            Err(QpInfoError::BadLength(_))
+        ));
+
+        let mut bad_qpn = message.clone();
+        bad_qpn[16..20].copy_from_slice(&0x0100_0000u32.to_be_bytes());
+        assert_eq!(
+            DestQpInfo::decode(&bad_qpn).err(),
+            Some(QpInfoError::InvalidQpn(0x0100_0000))
+        );
+
+        // An extension header that claims more bytes than were sent
+        let mut truncated = qp_info()
+            .encode(&[Extension {
+                kind: 1,
+                value: vec![0; 4],
+            }])
+            .unwrap();
+        truncated[42..44].copy_from_slice(&8u16.to_be_bytes());
+        assert_eq!(
+            DestQpInfo::decode(&truncated).err(),
+            Some(QpInfoError::TruncatedExtension { offset: 28 })
+        );
+    }
+
+    #[test]
+    pub fn remote_regions_roundtrip() {
+        let data = RemoteRegion {
+            name: "ring-data".to_owned(),
+            addr: 0x7f00_0000_1000,
+            len: 4096,
+            rkey: 0xabcd,
+        };
+        let indices = data.subregion("ring-indices", 0..16).unwrap();
+
+        let unknown = Extension {
+            kind: 0xffff,
+            value: vec![1, 2, 3],
+        };
+
+        let message = qp_info()
+            .encode(&[data.to_extension(), unknown, indices.to_extension()])
+            .unwrap();
+
+        let (_, extensions) = DestQpInfo::decode(&message).unwrap();
+
+        assert_eq!(
+            RemoteRegion::from_extensions(&extensions).unwrap(),
+            vec![data.clone(), indices]
+        );
+
+        assert_eq!(data.addr(4088, 8).unwrap(), 0x7f00_0000_1ff8);
+        assert!(matches!(
+            data.addr(4089, 8),
+            Err(RdmaError::RemoteRegionOutOfBounds { .. })
+        ));
+        assert!(data.addr(u64::MAX, 2).is_err());
+
+        assert!(matches!(
+            RemoteRegion::from_extensions(&[data.to_extension(), data.to_extension()]),
+            Err(QpInfoError::InvalidRemoteRegion(_))
+        ));
+    }
+
+    #[test]
+    pub fn resume_position_roundtrip() {
+        let message = qp_info()
+            .encode(&[resume_position_extension(1 << 40)])
+            .unwrap();
+
+        let (_, extensions) = DestQpInfo::decode(&message).unwrap();
+
+        assert_eq!(resume_position(&extensions), Ok(Some(1 << 40)));
+        assert_eq!(resume_position(&[]), Ok(None));
+
+        let short = Extension {
+            kind: EXTENSION_RESUME_POSITION,
+            value: vec![0; 4],
+        };
+
+        assert_eq!(
+            resume_position(&[short]),
+            Err(QpInfoError::InvalidResumePosition(4))
+        );
+    }
+}
@@ -43,4 +43,90 @@ pub mod tests {
             writer_thread.join().unwrap();
         });
     }
+
+    #[test]
+    pub fn ring_buffer_wrapping_chunks() {
+        use shared::ring_buffer::RingBufferConst;
+
+        let mut ring_buffer = RingBufferConst::<u64, 8>::new();
+        let mut ref_ring_buffer = ring_buffer.to_ref();
+        let (sender, receiver) = ref_ring_buffer.split();
+
+        // Move both indices to 6 so the next 4 slots cross the end
+        assert_eq!(sender.write(&[0; 6]), 6);
+        receiver.read_exact(6).unwrap().commit();
+
+        assert!(sender.try_reserve(4).is_none());
+
+        let mut writer = sender.try_reserve_wrapping(4).unwrap();
+        let (first, wrapped) = writer.as_mut_slices();
+        assert_eq!((first.len(), wrapped.len()), (2, 2));
+        for (i, slot) in first.iter_mut().chain(wrapped.iter_mut()).enumerate() {
+            slot.write(i as u64);
+        }
+        writer.commit();
+
+        assert!(receiver.read_exact(4).is_none());
+
+        let reader = receiver.read_exact_wrapping(4).unwrap();
+        assert!(reader.wraps());
+        assert_eq!(reader.as_slices(), (&[0, 1][..], &[2, 3][..]));
+        assert!(format!("{:?}", reader).contains("[0, 1], [2, 3]"));
+
+        assert!(sender.try_reserve_wrapping(5).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "wraps around the end of the ring buffer")]
+    pub fn ring_buffer_wrapping_chunk_deref() {
+        use shared::ring_buffer::RingBufferConst;
+
+        let mut ring_buffer = RingBufferConst::<u64, 8>::new();
+        let mut ref_ring_buffer = ring_buffer.to_ref();
+        let (sender, receiver) = ref_ring_buffer.split();
+
+        assert_eq!(sender.write(&[0; 6]), 6);
+        receiver.read_exact(6).unwrap().commit();
+        assert_eq!(sender.write(&[0; 4]), 4);
+
+        let reader = receiver.read_exact_wrapping(4).unwrap();
+        let _ = reader[0];
+    }
+
+    #[test]
+    pub fn ring_buffer_partial_commit() {
+        use shared::ring_buffer::RingBufferConst;
+
+        let mut ring_buffer = RingBufferConst::<u64, 8>::new();
+        let mut ref_ring_buffer = ring_buffer.to_ref();
+        let (sender, receiver) = ref_ring_buffer.split();
+
+        assert_eq!(sender.write(&[0; 6]), 6);
+        receiver.read_exact(6).unwrap().commit();
+
+        let mut writer = sender.reserve_wrapping(16);
+        assert_eq!(writer.len(), 8);
+        writer.truncate(6);
+
+        let (first, wrapped) = writer.slices_at(1, 3);
+        assert_eq!((first.len(), wrapped.len()), (1, 2));
+
+        for (i, slot) in first.iter_mut().chain(wrapped.iter_mut()).enumerate() {
+            slot.write(i as u64 + 1);
+        }
+        writer.slices_at(0, 1).0[0].write(0);
+
+        // Only the published prefix is readable
+        writer.commit_prefix(4);
+        let mut reader = receiver.read_wrapping(16);
+        assert_eq!(reader.len(), 4);
+        assert_eq!(reader.slices_at(1, 3), (&[1][..], &[2, 3][..]));
+
+        reader.truncate(2);
+        reader.commit();
+        assert_eq!(
+            receiver.read_wrapping(16).as_slices(),
+            (&[2, 3][..], &[][..])
+        );
+    }
 }
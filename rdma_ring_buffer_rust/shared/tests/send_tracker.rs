@@ -0,0 +1,68 @@
+#[cfg(test)]
+pub mod tests {
+    use shared::rdma_controller::send::SendTracker;
+
+    #[test]
+    pub fn signals_every_interval() {
+        let mut tracker = SendTracker::new(4);
+
+        let signaled: Vec<bool> = (0..8).map(|_| tracker.on_post(false, 64)).collect();
+        assert_eq!(
+            signaled,
+            [false, false, false, true, false, false, false, true]
+        );
+        assert_eq!(tracker.outstanding(), 8);
+
+        // A forced signal starts the interval over
+        assert!(!tracker.on_post(false, 64));
+        assert!(tracker.on_post(true, 64));
+        assert!(!tracker.on_post(false, 64));
+        assert_eq!(tracker.outstanding(), 11);
+
+        // Each completion frees its WR and the unsignaled ones before it
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 7);
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 3);
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 1);
+
+        // The unsignaled tail waits for a later signaled WR
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 1);
+    }
+
+    #[test]
+    pub fn signals_the_wr_filling_the_queue() {
+        let mut tracker = SendTracker::new(64);
+
+        let signaled: Vec<bool> = (0..4).map(|_| tracker.on_post(false, 4)).collect();
+        assert_eq!(signaled, [false, false, false, true]);
+        assert_eq!(tracker.outstanding(), 4);
+
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 0);
+    }
+
+    #[test]
+    pub fn records_wrs_posted_ahead_of_a_failure() {
+        let mut tracker = SendTracker::new(2);
+
+        // Signaling is decided on a copy, the post then fails at the third WR
+        let mut attempt = tracker.clone();
+        let signaled: Vec<bool> = (0..3).map(|_| attempt.on_post(false, 64)).collect();
+        assert_eq!(signaled, [false, true, false]);
+
+        tracker.record_posted(signaled[..2].iter().copied());
+        assert_eq!(tracker.outstanding(), 2);
+
+        // The failed WR left no trace, the interval goes on from the posted ones
+        assert!(!tracker.on_post(false, 64));
+        assert!(tracker.on_post(false, 64));
+
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 2);
+        tracker.on_completion();
+        assert_eq!(tracker.outstanding(), 0);
+    }
+}
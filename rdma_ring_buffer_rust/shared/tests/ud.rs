@@ -0,0 +1,25 @@
+#[cfg(test)]
+pub mod tests {
+    use shared::rdma_controller::ud::{Grh, GRH_LEN};
+
+    #[test]
+    pub fn grh_parse() {
+        let mut bytes = [0u8; GRH_LEN];
+        bytes[0..4].copy_from_slice(&0x6a01_2345u32.to_be_bytes());
+        bytes[4..6].copy_from_slice(&64u16.to_be_bytes());
+        bytes[6] = 0x1b;
+        bytes[7] = 1;
+        bytes[23] = 0xaa;
+        bytes[39] = 0xbb;
+
+        let grh = Grh::parse(&bytes).unwrap();
+
+        assert_eq!(grh.traffic_class(), 0xa0);
+        assert_eq!(grh.flow_label(), 0x12345);
+        assert_eq!(grh.payload_len, 64);
+        assert_eq!((grh.next_header, grh.hop_limit), (0x1b, 1));
+        assert_eq!((grh.sgid[15], grh.dgid[15]), (0xaa, 0xbb));
+
+        assert_eq!(Grh::parse(&bytes[..GRH_LEN - 1]), None);
+    }
+}
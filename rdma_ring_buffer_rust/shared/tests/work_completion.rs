@@ -0,0 +1,31 @@
+#[cfg(test)]
+pub mod tests {
+    use rdma_sys::{ibv_wc, ibv_wc_flags, ibv_wc_opcode, ibv_wc_status};
+    use shared::rdma_controller::work_completion::{WcOpcode, WcStatus, WorkCompletion};
+
+    #[test]
+    pub fn typed_status_and_opcode() {
+        let mut wc: ibv_wc = unsafe { std::mem::zeroed() };
+        wc.wr_id = 3;
+        wc.opcode = ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM;
+        wc.byte_len = 64;
+        wc.wc_flags = ibv_wc_flags::IBV_WC_WITH_IMM.0;
+        wc.imm_data_invalidated_rkey_union.imm_data = 7u32.to_be();
+
+        let wc = WorkCompletion::from(wc);
+
+        assert!(wc.is_success());
+        assert_eq!(wc.status(), WcStatus::Success);
+        assert_eq!(wc.opcode(), WcOpcode::RecvRdmaWithImm);
+        assert!(wc.opcode().is_recv());
+        assert_eq!((wc.wr_id(), wc.byte_len(), wc.imm_data()), (3, 64, Some(7)));
+
+        assert_eq!(
+            WcStatus::from(ibv_wc_status::IBV_WC_RETRY_EXC_ERR),
+            WcStatus::RetryExcErr
+        );
+        assert_eq!(WcStatus::from(1000), WcStatus::Other(1000));
+        assert!(!WcOpcode::from(ibv_wc_opcode::IBV_WC_RDMA_WRITE).is_recv());
+        assert!(WcOpcode::from(ibv_wc_opcode::IBV_WC_RECV | 0x10).is_recv());
+    }
+}